@@ -1,387 +1,1052 @@
 use std::{
-    process, 
     time::Duration,
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
 };
 
-use reqwest::{self, ClientBuilder};
-use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use reqwest::{self, ClientBuilder, RequestBuilder, StatusCode, Url};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    tungstenite::{
+        client::IntoClientRequest,
+        http::{HeaderName, HeaderValue},
+        Error as WsError,
+    },
+    Connector,
+    MaybeTlsStream,
+    WebSocketStream,
+};
+use rand::Rng;
+use crate::error::{Result, TynkerError};
 use serde::{Serialize, Deserialize};
 
-use crate::consts::NG_SKIP_WARN;
-use crate::global_state::GlobalState;
+use crate::cert_pinning;
+use crate::file_sync::{self, Manifest};
+use crate::upload::{self, ChunkWriter, Compression};
+use crate::consts::{BUILD_STATUS_PREFIX, NG_SKIP_WARN};
 use tynkerbase_universal::{
     constants::TYB_APIKEY_HTTP_HEADER, 
-    crypt_utils::{compression_utils, BinaryPacket}, 
     file_utils, 
-    netwk_utils::{NodeDiags, ProjConfig},
+    netwk_utils::{Node, NodeDiags, ProjConfig},
 };
 
-/// If this function returns an Ok(()) value, the node is up and running.
-pub async fn ping(endpoint: String) -> Result<()> {
-    let endpoint = parse_endpoint(endpoint)?;
-    let client = ClientBuilder::new()
-        .danger_accept_invalid_certs(true) // Disable TLS certificate validation
-        .timeout(std::time::Duration::from_secs(5))
-        .build()?;
-
-    let res = client
-        .get(format!("{}", endpoint))
-        .header(NG_SKIP_WARN, "easter egg here")
-        .send()
-        .await
-        .map_err(|e| anyhow!("Error sending https request: {e}"))?;
-
-    validate_response(res).await?;
-    Ok(())
+/// How long each kind of agent request may take before it's abandoned.
+#[derive(Debug, Clone)]
+pub struct Timeouts {
+    pub default: Duration,
+    /// Each attempt of a liveness check, kept short so a down node is reported quickly.
+    pub ping: Duration,
+    pub transfer: Duration,
+    pub build: Duration,
+    pub spawn: Duration,
+    pub purge: Duration,
+    pub diags: Duration,
+    /// Fetching logs without following them.
+    pub logs: Duration,
 }
 
-pub async fn get_id(endpoint: &str, tyb_key: &str) -> Result<String> {
-    let endpoint = parse_endpoint(endpoint)?;
-    let client = ClientBuilder::new()
-        .danger_accept_invalid_certs(true) 
-        .timeout(std::time::Duration::from_secs(5))
-        .build()?;
-
-    let res = client
-        .get(format!("{}", endpoint))
-        .header(TYB_APIKEY_HTTP_HEADER, tyb_key)
-        .header(NG_SKIP_WARN, "easter egg here")
-        .send()
-        .await
-        .map_err(|e| anyhow!("Error sending https request: {e}"))?;
-
-    let res = validate_response(res).await?;
-    let id = res.text().await
-        .map_err(|e| anyhow!("Error extracting text from response -> {}", e))?;
-
-    Ok(id)
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            default: Duration::from_secs(5),
+            ping: Duration::from_secs(2),
+            transfer: Duration::from_secs(10),
+            build: Duration::from_secs(2000),
+            spawn: Duration::from_secs(12),
+            purge: Duration::from_secs(12),
+            diags: Duration::from_secs(7),
+            logs: Duration::from_secs(30),
+        }
+    }
 }
 
-pub async fn create_proj(endpoint: &str, name: &str, tyb_key: &str) -> Result<()> {
-    let endpoint = parse_endpoint(endpoint)?;
-    let endpoint = format!("{}/files/proj/create-proj?name={}&confirm=false", endpoint, name);
-
-    let res = reqwest::ClientBuilder::new()
-        .danger_accept_invalid_certs(true)
-        .timeout(Duration::from_secs(5))
-        .build()? 
-        .get(endpoint)
-        .header(TYB_APIKEY_HTTP_HEADER, tyb_key)
-        .header(NG_SKIP_WARN, "easter egg here")
-        .send()
-        .await?;
-    
-    validate_response(res).await?;
-    Ok(())
+/// What changed on a node after syncing a project's files.
+#[derive(Debug, Clone, Default)]
+pub struct SyncSummary {
+    pub uploaded: usize,
+    pub deleted: usize,
+    pub unchanged: usize,
+    /// The whole project was uploaded, because the node didn't have it yet or its agent
+    /// doesn't support incremental sync.
+    pub full_upload: bool,
 }
 
-pub async fn delete_proj(endpoint: &str, name: &str, tyb_key: &str) -> Result<()> {
-    let endpoint = parse_endpoint(endpoint)?;
-    let endpoint = format!("{}/files/proj/delete-proj?name={}&confirm=false", endpoint, name);
-
-    let res = reqwest::ClientBuilder::new()
-        .danger_accept_invalid_certs(true)
-        .timeout(Duration::from_secs(5))
-        .build()? 
-        .get(endpoint)
-        .header(TYB_APIKEY_HTTP_HEADER, tyb_key)
-        .header(NG_SKIP_WARN, "easter egg here")
-        .send()
-        .await?;
-    
-    validate_response(res).await?;
-    Ok(())
+/// Optional features of a node's agent. Agents that predate the capabilities route have none.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Resumable uploads through the `/files/proj/upload/*` routes.
+    pub chunked_upload: bool,
+    /// The `get-manifest` and `delete-files` routes used for incremental sync.
+    pub manifest: bool,
 }
 
-pub async fn transfer_files(endpoint: &str, name: &str, tyb_key: &str, files: &file_utils::FileCollection) -> Result<()> {
-    let endpoint = parse_endpoint(endpoint)?;
+/// A deployed version of a project. The agent keeps the image of each release around
+/// so that it can be rolled back to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Release {
+    pub version: String,
+    pub created_at: String,
+    /// Whether this release's container is the one currently running.
+    pub active: bool,
+}
 
-    let mut packet = BinaryPacket::from(files)?;
-    // compression_utils::compress_brotli(&mut packet)?;
+/// Lifecycle actions that can be taken on a project's running container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerAction {
+    Stop,
+    Start,
+    Restart,
+    Remove,
+}
 
-    let packet_bin = bincode::serialize(&packet)
-        .map_err(|e| anyhow!("Error serializing binary packet: {}", e))?;
+impl ContainerAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContainerAction::Stop => "stop",
+            ContainerAction::Start => "start",
+            ContainerAction::Restart => "restart",
+            ContainerAction::Remove => "remove",
+        }
+    }
 
-    let client = ClientBuilder::new()
-        .danger_accept_invalid_certs(true) // Disable TLS certificate validation
-        .timeout(Duration::from_secs(10))
-        .build()?;
+    pub fn past_tense(&self) -> &'static str {
+        match self {
+            ContainerAction::Stop => "stopped",
+            ContainerAction::Start => "started",
+            ContainerAction::Restart => "restarted",
+            ContainerAction::Remove => "removed",
+        }
+    }
+}
 
-    let res = client
-        .post(format!("{}/files/proj/add-files-to-proj?name={}", endpoint, name))
-        .body(packet_bin)
-        .header(TYB_APIKEY_HTTP_HEADER, tyb_key)
-        .header(NG_SKIP_WARN, "easter egg here")
-        .send()
-        .await
-        .map_err(|e| anyhow!("Error sending https request: {e}"))?;
+/// Which part of a container's log to fetch.
+#[derive(Debug, Clone, Default)]
+pub struct LogOptions {
+    /// Keep the stream open and receive new lines as they're written.
+    pub follow: bool,
+    /// Only lines newer than this, as a duration (`10m`, `1h30m`) or a timestamp.
+    pub since: Option<String>,
+    /// Only the last `tail` lines.
+    pub tail: Option<u32>,
+}
 
-    validate_response(res).await?;
-    Ok(())
+/// Limits on the resources the project's container may use. Unset fields are unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Resources {
+    /// Number of cpus, e.g. `0.5`.
+    #[serde(default)]
+    pub cpus: Option<f64>,
+    /// Memory limit in megabytes.
+    #[serde(default)]
+    pub memory_mb: Option<u64>,
 }
 
-pub async fn deploy_proj(endpoint: &str, name: &str, tyb_key: &str, files: &file_utils::FileCollection) -> Result<()> {
-    purge_project(endpoint, name, tyb_key).await?;
-    create_proj(endpoint, name, tyb_key).await?;
-    transfer_files(endpoint, name, tyb_key, files).await?;
-    Ok(())
+impl Resources {
+    pub fn is_unlimited(&self) -> bool {
+        self.cpus.is_none() && self.memory_mb.is_none()
+    }
 }
 
-pub async fn list_projects(endpoint: &str, tyb_key: &str) -> Result<Vec<String>> {
-    let endpoint = parse_endpoint(endpoint)?;
-
-    let client = ClientBuilder::new()
-        .danger_accept_invalid_certs(true) 
-        .timeout(Duration::from_secs(5))
-        .build()?;
-
-    let res = client
-        .get(format!("{}/files/proj/list-projects", endpoint))
-        .header(TYB_APIKEY_HTTP_HEADER, tyb_key)
-        .header(NG_SKIP_WARN, "easter egg here")
-        .send()
-        .await
-        .map_err(|e| anyhow!("Error sending https request: {e}"))?;
-
-    let res = validate_response(res).await?;
-    let body = res.bytes().await
-        .map_err(|e| anyhow!("Error extracting bytes from response -> {}", e))?;
-    
-    let projects: Vec<String> = bincode::deserialize(&body)
-        .map_err(|e| anyhow!("Error deserializing data -> {}", e))?;
+/// Settings the container is started with, beyond what's in `ProjConfig`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SpawnOptions {
+    pub env: BTreeMap<String, String>,
+    pub resources: Resources,
+}
 
-    Ok(projects)
+/// A websocket to a process running in a project's container, see `exec` for the framing.
+pub type ExecSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Versions sort chronologically, so later deploys always have a greater version.
+pub fn new_release_version() -> String {
+    chrono::Utc::now().format("%Y%m%d%H%M%S").to_string()
 }
 
-/// Returns a vector of hashmaps of all the containers running on the machine.
-/// Each of the hashmaps have the following keys: `container_id`   `image`     `command`   `created_at`   `status`    `ports`     `names`
-pub async fn list_containers(endpoint: &str, tyb_key: &str) -> Result<Vec<HashMap<String, String>>> {
-    let endpoint = parse_endpoint(endpoint)?;
-
-    let name_mappings: HashMap<&str, &str> = vec![
-        ("CONTAINER ID", "container_id"),
-        ("IMAGE", "image"),
-        ("COMMAND", "command"),
-        ("CREATED AT", "created_at"),
-        ("STATUS", "status"),
-        ("PORTS", "ports"),
-        ("NAMES", "names"),
-    ].into_iter().collect();
-
-    let client = ClientBuilder::new()
-        .danger_accept_invalid_certs(true) 
-        .timeout(Duration::from_secs(5))
-        .build()?;
-
-    let res = client
-        .get(format!("{}/docker/proj/list-containers", endpoint))
-        .header(TYB_APIKEY_HTTP_HEADER, tyb_key)
-        .header(NG_SKIP_WARN, "easter egg here")
-        .send()
-        .await
-        .map_err(|e| anyhow!("Error sending https request: {e}"))?;
-
-    let res = validate_response(res).await?;
-
-    let text = res
-        .text()
-        .await
-        .map_err(|e| anyhow!("Error extracting text from response [fn list_containers] => {}", e))?;
-
-    let table = text
-        .split("\n")
-        .filter(|&s| s.trim().len() != 0)
-        .map(|s| s.to_string())
-        .collect::<Vec<String>>();
+/// Controls how idempotent agent requests are retried after transient failures.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Fraction (0.0 - 1.0) of each delay that is randomized so that clients don't retry in lockstep.
+    pub jitter: f64,
+    /// Response status codes that are worth retrying.
+    pub retry_statuses: Vec<u16>,
+    pub retry_connect_errors: bool,
+    pub retry_timeouts: bool,
+}
 
-    // Change keys 
-    let mut table = cvt_hashmap(table, "|||");
-    for t in table.iter_mut() {
-        for (&old_k, &new_k) in name_mappings.iter() {
-            if let Some(value) = t.remove(old_k) {
-                t.insert(new_k.to_string(), value);
-            }
-            else {
-                #[cfg(debug_assertions)] {
-                    println!("\n\nERROR FORMATTING RES: {:?}\n\n", t)
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(4),
+            jitter: 0.5,
+            retry_statuses: vec![408, 429, 502, 503, 504],
+            retry_connect_errors: true,
+            retry_timeouts: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that makes a single attempt.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// A few quick attempts for liveness checks, enough to ride out a dropped request through
+    /// ngrok without making a down node take long to report.
+    pub fn liveness() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(400),
+            ..Self::default()
+        }
+    }
+
+    fn is_retriable(&self, err: &TynkerError) -> bool {
+        match err {
+            TynkerError::Unreachable { .. } => self.retry_connect_errors,
+            TynkerError::Timeout { .. } => self.retry_timeouts,
+            TynkerError::Http { status, .. } => self.retry_statuses.contains(status),
+            _ => false,
+        }
+    }
+
+    /// Exponential backoff for the given attempt (starting at 1), with jitter applied.
+    fn delay(&self, attempt: u32) -> Duration {
+        let exp = self.base_delay.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let delay = exp.min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0) * rand::thread_rng().gen::<f64>();
+        delay.mul_f64(1.0 - jitter)
+    }
+}
+
+/// Client for the agent running on a single node. Cloning is cheap and clones share 
+/// the same connection pool, so one client should be reused for every request to a node.
+#[derive(Debug, Clone)]
+pub struct AgentClient {
+    node: Node,
+    base_url: String,
+    tyb_key: String,
+    client: reqwest::Client,
+    timeouts: Timeouts,
+    retry: RetryPolicy,
+    compression: Compression,
+    pinned_cert: Option<String>,
+    observed_cert: Arc<Mutex<Option<String>>>,
+    /// Fetched on first use and shared by clones.
+    capabilities: Arc<Mutex<Option<Capabilities>>>,
+}
+
+impl AgentClient {
+    /// `pinned_cert` is the sha256 fingerprint of the node's certificate. If it's `None`, any
+    /// certificate is accepted and its fingerprint can be read back with `observed_cert`.
+    pub fn new(node: &Node, tyb_key: &str, pinned_cert: Option<&str>) -> Result<Self> {
+        Self::with_timeouts(node, tyb_key, pinned_cert, Timeouts::default())
+    }
+
+    pub fn with_timeouts(node: &Node, tyb_key: &str, pinned_cert: Option<&str>, timeouts: Timeouts) -> Result<Self> {
+        let base_url = parse_endpoint(node.addr.clone())?;
+        let pinned_cert = pinned_cert.map(|s| s.to_string());
+        let observed_cert = Arc::new(Mutex::new(None));
+
+        let tls = cert_pinning::tls_config(pinned_cert.clone(), observed_cert.clone())
+            .map_err(|e| TynkerError::Other(e.to_string()))?;
+        let client = ClientBuilder::new()
+            .use_preconfigured_tls(tls)
+            .build()
+            .map_err(|e| TynkerError::Other(format!("Error building https client -> {}", e)))?;
+
+        Ok(AgentClient {
+            node: node.clone(),
+            base_url,
+            tyb_key: tyb_key.to_string(),
+            client,
+            timeouts,
+            retry: RetryPolicy::default(),
+            compression: Compression::default(),
+            pinned_cert,
+            observed_cert,
+            capabilities: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    pub fn node(&self) -> &Node {
+        &self.node
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.timeouts
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// Sets the compression used when uploading project files.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Fingerprint of the certificate presented by the node during the last TLS handshake.
+    pub fn observed_cert(&self) -> Option<String> {
+        self.observed_cert.lock().unwrap().clone()
+    }
+
+    /// Classifies an error from sending a request, reporting a certificate mismatch if the
+    /// node presented a certificate other than the pinned one.
+    fn request_error(&self, err: reqwest::Error) -> TynkerError {
+        match (&self.pinned_cert, self.observed_cert()) {
+            (Some(pinned), Some(observed)) if pinned != &observed => TynkerError::CertificateMismatch {
+                node: self.node.name.clone(),
+                pinned: pinned.clone(),
+                observed,
+            },
+            _ => TynkerError::from_reqwest(err, &self.node.name),
+        }
+    }
+
+    /// Sends the request built by `req`, retrying transient failures according to the retry
+    /// policy. Only use this for idempotent requests. The response is validated.
+    async fn send_with_retry(&self, req: impl Fn() -> RequestBuilder) -> Result<reqwest::Response> {
+        self.send_with_policy(&self.retry, req).await
+    }
+
+    /// Like `send_with_retry`, but with `policy` instead of the client's retry policy.
+    async fn send_with_policy(&self, policy: &RetryPolicy, req: impl Fn() -> RequestBuilder) -> Result<reqwest::Response> {
+        let mut attempt = 1;
+        loop {
+            let res = match req().send().await {
+                Ok(res) => validate_response(res).await,
+                Err(e) => Err(self.request_error(e)),
+            };
+
+            match res {
+                Err(e) if attempt < policy.max_attempts && policy.is_retriable(&e) => {
+                    #[cfg(debug_assertions)] println!("Attempt {} to node `{}` failed, retrying: {}", attempt, self.node.name, e);
+                    tokio::time::sleep(policy.delay(attempt)).await;
+                    attempt += 1;
                 }
+                Err(e) if attempt > 1 => return Err(TynkerError::Retried { attempts: attempt, source: Box::new(e) }),
+                res => return res,
             }
         }
     }
 
-    Ok(table)
-}
+    /// Builds an authenticated GET request to `path` on the agent.
+    fn get(&self, path: &str, timeout: Duration) -> RequestBuilder {
+        self.client
+            .get(format!("{}{}", self.base_url, path))
+            .timeout(timeout)
+            .header(TYB_APIKEY_HTTP_HEADER, &self.tyb_key)
+            .header(NG_SKIP_WARN, "easter egg here")
+    }
 
+    /// Builds an authenticated GET request without a timeout, for streams that stay open
+    /// until the caller stops reading.
+    fn get_stream(&self, path: &str) -> RequestBuilder {
+        self.client
+            .get(format!("{}{}", self.base_url, path))
+            .header(TYB_APIKEY_HTTP_HEADER, &self.tyb_key)
+            .header(NG_SKIP_WARN, "easter egg here")
+    }
 
-/// Returns a vector of hashmaps. Each hashmap has the following keys
-/// `container_id` `container`   `cpu_perc`     `mem_usage_limit`   `mem_perc`     `net_io`   `block_io`   `pids`
-pub async fn list_container_stats(endpoint: &str, tyb_key: &str) -> Result<Vec<HashMap<String, String>>> {
-    let endpoint = parse_endpoint(endpoint)?;
-
-    let name_mappings: HashMap<&str, &str> = vec![
-        ("CONTAINER ID", "container_id"),
-        ("CONTAINER", "container"),
-        ("CPU %", "cpu_perc"),
-        ("MEM USAGE / LIMIT", "mem_usage_limit"),
-        ("MEM %", "mem_perc"),
-        ("NET I/O", "net_io"),
-        ("BLOCK I/O", "block_io"),
-        ("PIDS", "pids")
-    ].into_iter().collect();
-
-    let client = ClientBuilder::new()
-        .danger_accept_invalid_certs(true) 
-        .timeout(Duration::from_secs(5))
-        .build()?;
-
-    let res = client
-        .get(format!("{}/docker/proj/list-container-stats", endpoint))
-        .header(TYB_APIKEY_HTTP_HEADER, tyb_key)
-        .header(NG_SKIP_WARN, "easter egg here")
-        .send()
-        .await
-        .map_err(|e| anyhow!("Error sending https request: {e}"))?;
-
-    let res = validate_response(res).await?;
-    let text = res.text().await
-        .map_err(|e| anyhow!("Error extracting bytes from response -> {}", e))?
-        .split("\n")
-        .filter(|&s| s.trim().len() != 0)
-        .map(|s| s.to_string())
-        .collect::<Vec<String>>();    
-
-    // Change keys 
-    let mut table = cvt_hashmap(text, "|||");
-    for t in table.iter_mut() {
-        for (&old_k, &new_k) in name_mappings.iter() {
-            if let Some(value) = t.remove(old_k) {
-                t.insert(new_k.to_string(), value);
+    /// Calls `on_line` for each line of a streamed response body as it arrives.
+    async fn read_lines(&self, mut res: reqwest::Response, mut on_line: impl FnMut(&str)) -> Result<()> {
+        let mut buf = vec![];
+        while let Some(chunk) = res.chunk().await.map_err(|e| self.request_error(e))? {
+            buf.extend_from_slice(&chunk);
+            while let Some(pos) = buf.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buf.drain(..=pos).collect();
+                on_line(String::from_utf8_lossy(&line).trim_end());
             }
         }
+        if buf.len() > 0 {
+            on_line(String::from_utf8_lossy(&buf).trim_end());
+        }
+        Ok(())
     }
 
-    Ok(table)
-}
+    /// Builds an authenticated POST request to `path` on the agent.
+    fn post(&self, path: &str, timeout: Duration) -> RequestBuilder {
+        self.client
+            .post(format!("{}{}", self.base_url, path))
+            .timeout(timeout)
+            .header(TYB_APIKEY_HTTP_HEADER, &self.tyb_key)
+            .header(NG_SKIP_WARN, "easter egg here")
+    }
+
+    /// If this function returns an Ok(()) value, the node is up and running. Uses the short
+    /// `ping` timeout and `RetryPolicy::liveness`, so callers find out quickly if a node is down.
+    pub async fn ping(&self) -> Result<()> {
+        let req = || self.client
+            .get(&self.base_url)
+            .timeout(self.timeouts.ping)
+            .header(NG_SKIP_WARN, "easter egg here");
+
+        self.send_with_policy(&RetryPolicy::liveness(), req).await?;
+        Ok(())
+    }
+
+    /// Asks the agent which optional features it supports.
+    pub async fn capabilities(&self) -> Result<Capabilities> {
+        if let Some(c) = self.capabilities.lock().unwrap().clone() {
+            return Ok(c);
+        }
+
+        let names: Vec<String> = match self.send_with_retry(|| self.get("/capabilities", self.timeouts.default)).await {
+            Ok(res) => {
+                let body = res.bytes().await
+                    .map_err(|e| TynkerError::Protocol(format!("Error extracting bytes from response -> {}", e)))?;
+                bincode::deserialize(&body)
+                    .map_err(|e| TynkerError::Protocol(format!("Error deserializing capabilities -> {}", e)))?
+            }
+            Err(TynkerError::Http { status: 404, .. }) => vec![],
+            Err(e) => return Err(e),
+        };
+        let caps = Capabilities {
+            chunked_upload: names.iter().any(|n| n == "chunked-upload"),
+            manifest: names.iter().any(|n| n == "manifest"),
+        };
+
+        *self.capabilities.lock().unwrap() = Some(caps.clone());
+        Ok(caps)
+    }
+
+    pub async fn get_id(&self) -> Result<String> {
+        let res = self.send_with_retry(|| self.get("", self.timeouts.default)).await?;
+        let id = res.text().await
+            .map_err(|e| TynkerError::Protocol(format!("Error extracting text from response -> {}", e)))?;
+
+        Ok(id)
+    }
+
+    pub async fn create_proj(&self, name: &str) -> Result<()> {
+        let res = self.get(&format!("/files/proj/create-proj?name={}&confirm=false", name), self.timeouts.default)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+        
+        validate_response(res).await?;
+        Ok(())
+    }
+
+    pub async fn delete_proj(&self, name: &str) -> Result<()> {
+        let res = self.get(&format!("/files/proj/delete-proj?name={}&confirm=false", name), self.timeouts.default)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+        
+        validate_response(res).await
+            .map_err(|e| project_error(e, name))?;
+        Ok(())
+    }
+
+    /// Returns the indices of the chunks of an upload that the node already has.
+    async fn upload_status(&self, name: &str, upload_id: &str) -> Result<Vec<u64>> {
+        let res = self.send_with_retry(|| self.get(&format!("/files/proj/upload/status?name={}&upload_id={}", name, upload_id), self.timeouts.default))
+            .await
+            .map_err(|e| project_error(e, name))?;
+
+        let body = res.bytes().await
+            .map_err(|e| TynkerError::Protocol(format!("Error extracting bytes from response -> {}", e)))?;
+
+        let received: Vec<u64> = bincode::deserialize(&body)
+            .map_err(|e| TynkerError::Protocol(format!("Error deserializing upload status -> {}", e)))?;
+
+        Ok(received)
+    }
+
+    /// Uploads go through the chunked upload routes, so this is checked before anything on the
+    /// node is changed.
+    async fn require_chunked_upload(&self) -> Result<()> {
+        if !self.capabilities().await?.chunked_upload {
+            return Err(TynkerError::Protocol(
+                "the agent doesn't support chunked uploads, update it to deploy with this version of tyb".to_string()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Compresses the files and uploads them in chunks as they're produced, skipping chunks the
+    /// node already received from an earlier, interrupted upload. `on_progress` is called with the
+    /// compressed bytes the node has so far and the size of the whole upload, which is estimated
+    /// from the compression ratio until the last chunk is sent.
+    pub async fn upload_files(&self, name: &str, files: &file_utils::FileCollection, on_progress: &mut (dyn FnMut(u64, u64) + Send)) -> Result<()> {
+        self.require_chunked_upload().await?;
+
+        let upload_id = upload::upload_id(files, self.compression);
+        let received = self.upload_status(name, &upload_id).await?
+            .into_iter()
+            .collect::<HashSet<u64>>();
+
+        let size = files.contents.iter().map(|c| c.len() as u64).sum();
+        let mut read: u64 = 0;
+        let mut sent: u64 = 0;
+        on_progress(sent, upload::estimated_size(sent, read, size));
+
+        let mut writer = ChunkWriter::new(self.compression)?;
+        let mut index: u64 = 0;
+        for (path, contents) in files.files.iter().zip(files.contents.iter()) {
+            read += contents.len() as u64;
+            for chunk in writer.add_file(path, contents)? {
+                sent += self.upload_chunk(name, &upload_id, index, chunk, &received).await?;
+                index += 1;
+                on_progress(sent, upload::estimated_size(sent, read, size));
+            }
+        }
+        for chunk in writer.finish()? {
+            sent += self.upload_chunk(name, &upload_id, index, chunk, &received).await?;
+            index += 1;
+        }
+        on_progress(sent, sent);
+
+        let path = format!(
+            "/files/proj/upload/finish?name={}&upload_id={}&chunks={}&compression={}", 
+            name, upload_id, index, self.compression.as_str()
+        );
+        let res = self.post(&path, self.timeouts.transfer)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+
+        validate_response(res).await
+            .map_err(|e| project_error(e, name))?;
+        Ok(())
+    }
+
+    /// Sends chunk `index` of an upload, unless it's in `received`. Returns the chunk's size,
+    /// since either way the node has it afterwards.
+    async fn upload_chunk(&self, name: &str, upload_id: &str, index: u64, chunk: Vec<u8>, received: &HashSet<u64>) -> Result<u64> {
+        let len = chunk.len() as u64;
+        if received.contains(&index) {
+            return Ok(len);
+        }
+        let path = format!("/files/proj/upload/chunk?name={}&upload_id={}&index={}", name, upload_id, index);
+        let chunk = Bytes::from(chunk);
+        self.send_with_retry(|| self.post(&path, self.timeouts.transfer).body(chunk.clone())).await?;
+        Ok(len)
+    }
+
+    /// Replaces the node's copy of the project with `files`. Fails without touching the node if
+    /// its agent can't take the upload.
+    pub async fn deploy_proj(&self, name: &str, files: &file_utils::FileCollection, on_progress: &mut (dyn FnMut(u64, u64) + Send)) -> Result<()> {
+        self.require_chunked_upload().await?;
+        self.purge_project(name).await?;
+        self.create_proj(name).await?;
+        self.upload_files(name, files, on_progress).await?;
+        Ok(())
+    }
+
+    /// Returns the hash of every file the node has for the project.
+    pub async fn get_manifest(&self, name: &str) -> Result<Manifest> {
+        let res = self.send_with_retry(|| self.get(&format!("/files/proj/get-manifest?name={}", name), self.timeouts.default))
+            .await
+            .map_err(|e| project_error(e, name))?;
+
+        let body = res.bytes().await
+            .map_err(|e| TynkerError::Protocol(format!("Error extracting bytes from response -> {}", e)))?;
+
+        let manifest: Manifest = bincode::deserialize(&body)
+            .map_err(|e| TynkerError::Protocol(format!("Error deserializing manifest -> {}", e)))?;
 
-/// Returns a vector of hashmaps. Each hashmap has the following keys
-/// `container_id` `container`   `cpu_perc`     `mem_usage_limit`   `mem_perc`     `net_io`   `block_io`   `pids`
-/// `image`     `command`   `created_at`   `status`    `ports`     `names`
-pub async fn list_container_stats_all(endpoint: impl AsRef<str>, tyb_key: impl AsRef<str>) -> Result<Vec<HashMap<String, String>>> {
-    let endpoint = endpoint.as_ref();
-    let tyb_key = tyb_key.as_ref();
-    let (lst, stats) = tokio::join!(
-        list_containers(endpoint, tyb_key),
-        list_container_stats(endpoint, tyb_key),
-    );
-
-    let mut result = lst?;
-    if let Ok(stats) = stats {
-        for stat in stats {
-            let s_id = stat.get("container_id").unwrap();
-            for r in result.iter_mut() {
-                let r_id = r.get("container_id").unwrap();
-                if s_id == r_id {
-                    r.extend(stat);
-                    break;
+        Ok(manifest)
+    }
+
+    pub async fn delete_files(&self, name: &str, paths: &[String]) -> Result<()> {
+        let data = bincode::serialize(paths)
+            .map_err(|e| TynkerError::Protocol(format!("Failed to serialize paths [fn delete_files] -> {}", e)))?;
+
+        let res = self.post(&format!("/files/proj/delete-files?name={}", name), self.timeouts.default)
+            .body(data)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+
+        validate_response(res).await
+            .map_err(|e| project_error(e, name))?;
+        Ok(())
+    }
+
+    /// Uploads only the files that differ from what the node already has and deletes the ones
+    /// that were removed locally. Falls back to `deploy_proj` if the agent doesn't support
+    /// incremental sync, and to uploading everything if the node doesn't have the project yet.
+    /// Agents that can't take chunked uploads fail before anything on the node is changed.
+    pub async fn sync_proj(&self, name: &str, files: &file_utils::FileCollection, local: &Manifest, on_progress: &mut (dyn FnMut(u64, u64) + Send)) -> Result<SyncSummary> {
+        let full_upload = SyncSummary {
+            uploaded: local.len(),
+            full_upload: true,
+            ..Default::default()
+        };
+
+        self.require_chunked_upload().await?;
+
+        // Older agents 404 on the manifest route too, so that can only mean the project is
+        // missing once the agent says it has the route
+        if !self.capabilities().await?.manifest {
+            self.deploy_proj(name, files, on_progress).await?;
+            return Ok(full_upload);
+        }
+        let remote = match self.get_manifest(name).await {
+            Ok(m) => m,
+            Err(TynkerError::ProjectNotFound(_)) => {
+                self.create_proj(name).await?;
+                self.upload_files(name, files, on_progress).await?;
+                return Ok(full_upload);
+            }
+            Err(e) => return Err(e),
+        };
+
+        let plan = file_sync::plan(local, &remote);
+        if plan.upload.len() > 0 {
+            self.upload_files(name, &file_sync::subset(files, &plan.upload), on_progress).await?;
+        }
+        else {
+            on_progress(0, 0);
+        }
+        if plan.delete.len() > 0 {
+            self.delete_files(name, &plan.delete).await?;
+        }
+
+        Ok(SyncSummary {
+            uploaded: plan.upload.len(),
+            deleted: plan.delete.len(),
+            unchanged: plan.unchanged,
+            full_upload: false,
+        })
+    }
+
+    pub async fn list_projects(&self) -> Result<Vec<String>> {
+        let res = self.send_with_retry(|| self.get("/files/proj/list-projects", self.timeouts.default)).await?;
+        let body = res.bytes().await
+            .map_err(|e| TynkerError::Protocol(format!("Error extracting bytes from response -> {}", e)))?;
+        
+        let projects: Vec<String> = bincode::deserialize(&body)
+            .map_err(|e| TynkerError::Protocol(format!("Error deserializing data -> {}", e)))?;
+
+        Ok(projects)
+    }
+
+    /// Returns a vector of hashmaps of all the containers running on the machine.
+    /// Each of the hashmaps have the following keys: `container_id`   `image`     `command`   `created_at`   `status`    `ports`     `names`
+    pub async fn list_containers(&self) -> Result<Vec<HashMap<String, String>>> {
+        let name_mappings: HashMap<&str, &str> = vec![
+            ("CONTAINER ID", "container_id"),
+            ("IMAGE", "image"),
+            ("COMMAND", "command"),
+            ("CREATED AT", "created_at"),
+            ("STATUS", "status"),
+            ("PORTS", "ports"),
+            ("NAMES", "names"),
+        ].into_iter().collect();
+
+        let res = self.send_with_retry(|| self.get("/docker/proj/list-containers", self.timeouts.default)).await?;
+
+        let text = res
+            .text()
+            .await
+            .map_err(|e| TynkerError::Protocol(format!("Error extracting text from response [fn list_containers] => {}", e)))?;
+
+        let table = text
+            .split("\n")
+            .filter(|&s| s.trim().len() != 0)
+            .map(|s| s.to_string())
+            .collect::<Vec<String>>();
+
+        // Change keys 
+        let mut table = cvt_hashmap(table, "|||");
+        for t in table.iter_mut() {
+            for (&old_k, &new_k) in name_mappings.iter() {
+                if let Some(value) = t.remove(old_k) {
+                    t.insert(new_k.to_string(), value);
+                }
+                else {
+                    #[cfg(debug_assertions)] {
+                        println!("\n\nERROR FORMATTING RES: {:?}\n\n", t)
+                    }
                 }
             }
         }
+
+        Ok(table)
+    }
+
+    /// Whether a container of the project is currently up on the node.
+    pub async fn container_running(&self, name: &str) -> Result<bool> {
+        let containers = self.list_containers().await?;
+        let running = containers.iter().any(|c| {
+            // Names and images have to match exactly, `web` mustn't match a running `web-old`
+            let is_proj = c.get("names")
+                .map(|n| n.split(',').any(|n| n.trim().trim_start_matches('/') == name))
+                .unwrap_or(false)
+                || c.get("image").map(|i| image_repository(i) == name).unwrap_or(false);
+            let is_up = c.get("status").map(|s| s.starts_with("Up")).unwrap_or(false);
+            is_proj && is_up
+        });
+        Ok(running)
+    }
+
+
+    /// Returns a vector of hashmaps. Each hashmap has the following keys
+    /// `container_id` `container`   `cpu_perc`     `mem_usage_limit`   `mem_perc`     `net_io`   `block_io`   `pids`
+    pub async fn list_container_stats(&self) -> Result<Vec<HashMap<String, String>>> {
+        let name_mappings: HashMap<&str, &str> = vec![
+            ("CONTAINER ID", "container_id"),
+            ("CONTAINER", "container"),
+            ("CPU %", "cpu_perc"),
+            ("MEM USAGE / LIMIT", "mem_usage_limit"),
+            ("MEM %", "mem_perc"),
+            ("NET I/O", "net_io"),
+            ("BLOCK I/O", "block_io"),
+            ("PIDS", "pids")
+        ].into_iter().collect();
+
+        let res = self.send_with_retry(|| self.get("/docker/proj/list-container-stats", self.timeouts.default)).await?;
+        let text = res.text().await
+            .map_err(|e| TynkerError::Protocol(format!("Error extracting bytes from response -> {}", e)))?
+            .split("\n")
+            .filter(|&s| s.trim().len() != 0)
+            .map(|s| s.to_string())
+            .collect::<Vec<String>>();    
+
+        // Change keys 
+        let mut table = cvt_hashmap(text, "|||");
+        for t in table.iter_mut() {
+            for (&old_k, &new_k) in name_mappings.iter() {
+                if let Some(value) = t.remove(old_k) {
+                    t.insert(new_k.to_string(), value);
+                }
+            }
+        }
+
+        Ok(table)
+    }
+
+    /// Returns a vector of hashmaps. Each hashmap has the following keys
+    /// `container_id` `container`   `cpu_perc`     `mem_usage_limit`   `mem_perc`     `net_io`   `block_io`   `pids`
+    /// `image`     `command`   `created_at`   `status`    `ports`     `names`
+    pub async fn list_container_stats_all(&self) -> Result<Vec<HashMap<String, String>>> {
+        let (lst, stats) = tokio::join!(
+            self.list_containers(),
+            self.list_container_stats(),
+        );
+
+        let mut result = lst?;
+        if let Ok(stats) = stats {
+            for stat in stats {
+                let s_id = stat.get("container_id").unwrap();
+                for r in result.iter_mut() {
+                    let r_id = r.get("container_id").unwrap();
+                    if s_id == r_id {
+                        r.extend(stat);
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Parse the `ports` field to make it more readable
+        for cont in result.iter_mut() {
+            let p = match cont.get("ports") {
+                Some(p) => p,
+                None => break,
+            };
+            cont.insert("ports".to_string(), parse_port_str(p));
+        }
+        
+
+        Ok(result)
+    }
+
+    /// Builds the project's image, tagged with the release `version`.
+    pub async fn build_img(&self, name: &str, version: &str) -> Result<()> {
+        let res = self.get(&format!("/docker/proj/build-img?name={}&version={}", name, version), self.timeouts.build)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+
+        validate_response(res).await
+            .map_err(|e| build_error(e, name))?;
+        Ok(())
+    }
+
+    /// Builds the project's image, calling `on_line` with each line of build output as the agent
+    /// streams it back. Falls back to `build_img` for agents that don't support streaming.
+    pub async fn build_img_streaming(&self, name: &str, version: &str, mut on_line: impl FnMut(&str)) -> Result<()> {
+        let res = self.get(&format!("/docker/proj/build-img-stream?name={}&version={}", name, version), self.timeouts.build)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+
+        if res.status() == StatusCode::NOT_FOUND {
+            on_line("Agent does not support streaming build logs, waiting for the build to finish ...");
+            return self.build_img(name, version).await;
+        }
+        let res = validate_response(res).await
+            .map_err(|e| build_error(e, name))?;
+
+        // Keep the tail of the log to report if the build fails
+        let mut tail: Vec<String> = vec![];
+        let mut status = None;
+
+        self.read_lines(res, |line| {
+            if let Some(s) = line.strip_prefix(BUILD_STATUS_PREFIX) {
+                status = Some(s.trim().to_string());
+                return;
+            }
+            on_line(line);
+            tail.push(line.to_string());
+            if tail.len() > 20 {
+                tail.remove(0);
+            }
+        }).await?;
+
+        match status.as_deref() {
+            Some("ok") => Ok(()),
+            Some(_) => Err(TynkerError::BuildFailed { project: name.to_string(), output: tail.join("\n") }),
+            None => Err(TynkerError::Protocol("build log ended without a status, the connection may have dropped".to_string())),
+        }
     }
 
-    // Parse the `ports` field to make it more readable
-    for cont in result.iter_mut() {
-        let p = match cont.get("ports") {
-            Some(p) => p,
-            None => break,
+    /// Starts a container from the image of release `version` with the environment variables
+    /// and resource limits in `opts`. The agent only stops the previous release's container once
+    /// the new one has started.
+    pub async fn spawn_container(&self, config: &ProjConfig, version: &str, opts: &SpawnOptions) -> Result<()> {
+        // Agents that predate spawn options only know the plain config route
+        let plain = opts.env.len() == 0 && opts.resources.is_unlimited();
+        let (route, data) = if plain {
+            ("spawn-container", bincode::serialize(config))
+        }
+        else {
+            ("spawn-container-with-options", bincode::serialize(&(config, opts)))
         };
-        cont.insert("ports".to_string(), parse_port_str(p));
+        let data = data
+            .map_err(|e| TynkerError::Protocol(format!("Failed to serialize config [fn spawn_container] -> {}", e)))?;
+
+        let res = self.post(&format!("/docker/proj/{}?version={}", route, version), self.timeouts.spawn)
+            .body(data)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+
+        match validate_response(res).await {
+            Err(TynkerError::Http { status: 404, .. }) if !plain => Err(TynkerError::Protocol(
+                "the agent doesn't support environment variables or resource limits".to_string()
+            )),
+            Err(e) => Err(e),
+            Ok(_) => Ok(()),
+        }
     }
-    
 
-    Ok(result)
-}
+    /// Stops, starts, restarts or removes the project's container. Removing the container
+    /// keeps the project's files and release images.
+    pub async fn container_action(&self, name: &str, action: ContainerAction) -> Result<()> {
+        let path = format!("/docker/proj/{}-container?name={}", action.as_str(), name);
+        let res = self.get(&path, self.timeouts.spawn)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+
+        validate_response(res).await
+            .map_err(|e| project_error(e, name))?;
+        Ok(())
+    }
 
-pub async fn build_img (endpoint: &str, name: &str, tyb_key: &str) -> Result<()> {
-    let endpoint = parse_endpoint(endpoint)?;
+    /// Streams the stdout and stderr of the project's container, calling `on_line` for each 
+    /// line. When following, this only returns once the container stops or the connection drops.
+    pub async fn stream_logs(&self, name: &str, opts: &LogOptions, on_line: impl FnMut(&str)) -> Result<()> {
+        let mut query = vec![
+            ("name", name.to_string()),
+            ("follow", opts.follow.to_string()),
+        ];
+        if let Some(ref since) = opts.since {
+            query.push(("since", since.clone()));
+        }
+        if let Some(tail) = opts.tail {
+            query.push(("tail", tail.to_string()));
+        }
 
-    let client = ClientBuilder::new()
-        .danger_accept_invalid_certs(true) 
-        .timeout(Duration::from_secs(2000))
-        .build()?;
+        let req = if opts.follow {
+            self.get_stream("/docker/proj/logs")
+        }
+        else {
+            self.get("/docker/proj/logs", self.timeouts.logs)
+        };
+        let res = req
+            .query(&query)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+
+        let res = validate_response(res).await
+            .map_err(|e| project_error(e, name))?;
+        self.read_lines(res, on_line).await
+    }
 
-    let res = client
-        .get(format!("{}/docker/proj/build-img?name={}", endpoint, name))
-        .header(TYB_APIKEY_HTTP_HEADER, tyb_key)
-        .header(NG_SKIP_WARN, "easter egg here")
-        .send()
-        .await
-        .map_err(|e| anyhow!("Error sending https request [fn build_img]: {e}"))?;
+    /// Opens a websocket to `cmd` running in the project's container. With `tty` the agent 
+    /// allocates a pseudo terminal of `size` (columns, rows).
+    pub async fn exec_socket(&self, name: &str, cmd: &[String], tty: bool, size: Option<(u16, u16)>) -> Result<ExecSocket> {
+        let ws_base = if let Some(rest) = self.base_url.strip_prefix("https://") {
+            format!("wss://{}", rest)
+        }
+        else if let Some(rest) = self.base_url.strip_prefix("http://") {
+            format!("ws://{}", rest)
+        }
+        else {
+            return Err(TynkerError::InvalidEndpoint(format!("`{}` is not an http(s) url", self.base_url)));
+        };
 
-    validate_response(res).await?;
-    Ok(())
-}
+        let mut url = Url::parse(&format!("{}/docker/proj/exec", ws_base))
+            .map_err(|e| TynkerError::InvalidEndpoint(e.to_string()))?;
+        url.query_pairs_mut()
+            .append_pair("name", name)
+            .append_pair("cmd", &serde_json::to_string(cmd)?)
+            .append_pair("tty", &tty.to_string());
+        if let Some((cols, rows)) = size {
+            url.query_pairs_mut()
+                .append_pair("cols", &cols.to_string())
+                .append_pair("rows", &rows.to_string());
+        }
+
+        let mut request = url.as_str()
+            .into_client_request()
+            .map_err(|e| TynkerError::Other(format!("Error building exec request -> {}", e)))?;
+        let key = HeaderValue::from_str(&self.tyb_key)
+            .map_err(|e| TynkerError::Other(format!("Api key is not a valid header value -> {}", e)))?;
+        let headers = request.headers_mut();
+        headers.insert(HeaderName::from_bytes(TYB_APIKEY_HTTP_HEADER.as_bytes()).unwrap(), key);
+        headers.insert(HeaderName::from_bytes(NG_SKIP_WARN.as_bytes()).unwrap(), HeaderValue::from_static("easter egg here"));
+
+        let tls = cert_pinning::tls_config(self.pinned_cert.clone(), self.observed_cert.clone())
+            .map_err(|e| TynkerError::Other(e.to_string()))?;
+        let connect = tokio_tungstenite::connect_async_tls_with_config(
+            request, 
+            None, 
+            false, 
+            Some(Connector::Rustls(Arc::new(tls))),
+        );
+
+        let (socket, _) = tokio::time::timeout(self.timeouts.default, connect)
+            .await
+            .map_err(|_| TynkerError::Timeout { target: self.node.name.clone() })?
+            .map_err(|e| self.ws_error(e, name))?;
+        Ok(socket)
+    }
+
+    /// Like `request_error`, for errors from opening a websocket.
+    fn ws_error(&self, err: WsError, name: &str) -> TynkerError {
+        if let (Some(pinned), Some(observed)) = (&self.pinned_cert, self.observed_cert()) {
+            if pinned != &observed {
+                return TynkerError::CertificateMismatch { node: self.node.name.clone(), pinned: pinned.clone(), observed };
+            }
+        }
+        match err {
+            WsError::Http(res) => {
+                let status = res.status().as_u16();
+                let body = res.body()
+                    .as_ref()
+                    .map(|b| String::from_utf8_lossy(b).to_string())
+                    .unwrap_or("NONE".to_string());
+                match status {
+                    401 | 403 => TynkerError::Unauthorized(body),
+                    404 => TynkerError::ProjectNotFound(name.to_string()),
+                    _ => TynkerError::Http { status, body },
+                }
+            }
+            WsError::Io(e) => TynkerError::Unreachable { target: self.node.name.clone(), reason: e.to_string() },
+            WsError::Tls(e) => TynkerError::Unreachable { target: self.node.name.clone(), reason: e.to_string() },
+            e => TynkerError::Protocol(e.to_string()),
+        }
+    }
 
-pub async fn spawn_container(endpoint: &str, config: &ProjConfig, tyb_key: &str) -> Result<()> {
-    let endpoint = parse_endpoint(endpoint)?;
+    /// Lists the releases of a project the node still has images for, oldest first.
+    pub async fn list_releases(&self, name: &str) -> Result<Vec<Release>> {
+        let res = self.send_with_retry(|| self.get(&format!("/docker/proj/list-releases?name={}", name), self.timeouts.default))
+            .await
+            .map_err(|e| project_error(e, name))?;
 
-    let data = bincode::serialize(config)
-        .map_err(|e| anyhow!("Failed to serialize config [fn spawn_container] -> {}", e))?;
+        let body = res.bytes().await
+            .map_err(|e| TynkerError::Protocol(format!("Error extracting bytes from response -> {}", e)))?;
 
-    let client = ClientBuilder::new()
-        .danger_accept_invalid_certs(true) 
-        .timeout(Duration::from_secs(12))
-        .build()?;
+        let releases: Vec<Release> = bincode::deserialize(&body)
+            .map_err(|e| TynkerError::Protocol(format!("Error deserializing releases -> {}", e)))?;
 
-    let res = client
-        .post(format!("{}/docker/proj/spawn-container", endpoint))
-        .header(TYB_APIKEY_HTTP_HEADER, tyb_key)
-        .header(NG_SKIP_WARN, "easter egg here")
-        .body(data)
-        .send()
-        .await
-        .map_err(|e| anyhow!("Error sending https request [fn spawn_container]: {e}"))?;
+        Ok(releases)
+    }
 
-    validate_response(res).await?;
-    Ok(())
-}
+    /// Restarts the project's container from the image of an earlier release.
+    pub async fn rollback(&self, name: &str, version: &str) -> Result<()> {
+        let res = self.get(&format!("/docker/proj/rollback?name={}&version={}", name, version), self.timeouts.spawn)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+
+        validate_response(res).await
+            .map_err(|e| project_error(e, name))?;
+        Ok(())
+    }
+
+    pub async fn purge_project(&self, name: &str) -> Result<()> {
+        #[cfg(debug_assertions)] println!("ENDPOINT: {}", self.base_url);
 
-pub async fn purge_project(endpoint: &str, name: &str, tyb_key: &str) -> Result<()> {
-    let endpoint = parse_endpoint(endpoint)?;
+        let res = self.get(&format!("/files/proj/purge-project?name={}", name), self.timeouts.purge)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
 
-    #[cfg(debug_assertions)] println!("ENDPOINT: {}", endpoint);
+        #[cfg(debug_assertions)] println!("request sent successfully");
 
-    let client = ClientBuilder::new()
-        .danger_accept_invalid_certs(true) 
-        .timeout(Duration::from_secs(12))
-        .build()?;
 
-    let res = client
-        .get(format!("{}/files/proj/purge-project?name={}", endpoint, name))
-        .header(TYB_APIKEY_HTTP_HEADER, tyb_key)
-        .header(NG_SKIP_WARN, "easter egg here")
-        .send()
-        .await
-        .map_err(|e| anyhow!("Error sending https request [fn purge_project]: {e}"))?;
+        validate_response(res).await?;
 
-    #[cfg(debug_assertions)] println!("request sent successfully");
+        #[cfg(debug_assertions)] println!("purge project validated");
 
+        Ok(())
+    }
 
-    validate_response(res).await?;
+    pub async fn get_diags(&self) -> Result<NodeDiags> {
+        let res = self.send_with_retry(|| self.get("/diags/get-diags", self.timeouts.diags)).await?;
+        let text = res
+            .text()
+            .await
+            .map_err(|e| TynkerError::Protocol(format!("Error extracting text from https response [fn get_diags]: {e}")))?;
 
-    #[cfg(debug_assertions)] println!("purge project validated");
+        let diags: NodeDiags = serde_json::from_str(&text)
+            .map_err(|e| TynkerError::Protocol(format!("Error deserializing json response from agent [fn get_diags]: {e}")))?;
 
-    Ok(())
+        Ok(diags)
+    }
 }
 
-pub async fn check_node_states(state: &GlobalState) -> HashMap<String, bool> {
+/// Pings every client concurrently, keyed by node id. The certificates the clients observe
+/// can be pinned afterwards with `GlobalState::pin_observed_certs`.
+pub async fn check_node_states(clients: &[AgentClient]) -> HashMap<String, bool> {
     let mut res = HashMap::new();
 
     let mut futures = vec![];
-    for n in state.nodes.iter() {
-        let f = ping(n.addr.clone());
-        let handle = tokio::spawn(f);
-        futures.push((n.node_id.clone(), handle));
+    for client in clients {
+        let c = client.clone();
+        let handle = tokio::spawn(async move { c.ping().await });
+        futures.push((client.node().node_id.clone(), handle));
     }
 
     for (n, h) in futures {
@@ -396,33 +1061,6 @@ pub async fn check_node_states(state: &GlobalState) -> HashMap<String, bool> {
     res
 }
 
-pub async fn get_diags(endpoint: &str, tyb_key: &str) -> Result<NodeDiags> {
-    let endpoint = parse_endpoint(endpoint)?;
-
-    let client = ClientBuilder::new()
-        .danger_accept_invalid_certs(true) 
-        .timeout(Duration::from_secs(7))
-        .build()?;
-
-    let res = client
-        .get(format!("{}/diags/get-diags", &endpoint))
-        .header(TYB_APIKEY_HTTP_HEADER, tyb_key)
-        .header(NG_SKIP_WARN, "easter egg here")
-        .send()
-        .await
-        .map_err(|e| anyhow!("Error sending https request [fn get_diags]: {e}"))?;
-
-    let text = res
-        .text()
-        .await
-        .map_err(|e| anyhow!("Error extracting text from https response [fn get_diags]: {e}"))?;
-
-    let diags: NodeDiags = serde_json::from_str(&text)
-        .map_err(|e| anyhow!("Error deserializing json response from agent [fn get_diags]: {e}"))?;
-
-    Ok(diags)
-}
-
 
 fn parse_endpoint(endpoint: impl Into<String>) -> Result<String> {
     let endpoint: String = endpoint.into();
@@ -438,18 +1076,45 @@ fn parse_endpoint(endpoint: impl Into<String>) -> Result<String> {
         return Ok(format!("https://{}:7462", endpoint))
     }
 
-    Err(anyhow!("Endpoint `{}` is in an unexpected format.", endpoint))
+    Err(TynkerError::InvalidEndpoint(format!("Endpoint `{}` is in an unexpected format.", endpoint)))
 }
 
 pub async fn validate_response(response: reqwest::Response) -> Result<reqwest::Response> {
     if !response.status().is_success() {
         let status = response.status();
-        let mut text = response.text().await.unwrap_or("NONE".to_string());
-        return Err(anyhow!("\nNon 200 response from node\nStatus Code: {:?}\nText Body: {}\n", status, text));
+        let text = response.text().await.unwrap_or("NONE".to_string());
+        return Err(match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => TynkerError::Unauthorized(text),
+            _ => TynkerError::Http { status: status.as_u16(), body: text },
+        });
     }
     Ok(response)
 }
 
+/// Agents respond with a 404 when a request references a project that doesn't exist.
+fn project_error(err: TynkerError, name: &str) -> TynkerError {
+    match err {
+        TynkerError::Http { status: 404, .. } => TynkerError::ProjectNotFound(name.to_string()),
+        e => e,
+    }
+}
+
+fn build_error(err: TynkerError, name: &str) -> TynkerError {
+    match project_error(err, name) {
+        TynkerError::Http { body, .. } => TynkerError::BuildFailed { project: name.to_string(), output: body },
+        e => e,
+    }
+}
+
+/// Image name without its tag, e.g. `web` for `web:20240101000000`. A `:` before the last `/`
+/// is a registry's port, not a tag.
+fn image_repository(image: &str) -> &str {
+    match image.rsplit_once(':') {
+        Some((repo, tag)) if !tag.contains('/') => repo,
+        _ => image,
+    }
+}
+
 /// Parses the default port string returned by docker to XXXX/tcp where XXXX is the host's exposed port
 fn parse_port_str(port: &str) -> String {
     let port = match port.split_once(":::") {
@@ -1,88 +1,94 @@
 use tynkerbase_universal::{
-    self, 
     crypt_utils::hash_utils,
     netwk_utils::Node,
 };
 
-use crate::consts::AUTH_ENDPOINT;
 use crate::agent_interface::validate_response;
-use reqwest;
-use bincode;
-use anyhow::{anyhow, Result};
+use crate::error::{Result, TynkerError};
 
-pub async fn login(email: &str, password: &str) -> Result<String> {
+/// Body of the auth server's response to a login with the wrong password.
+const INCORRECT_PASSWORD: &str = "Incorrect password";
+
+pub async fn login(auth_endpoint: &str, email: &str, password: &str) -> Result<String> {
     let pass_sha256 = hash_utils::sha256(password);
+    let pass_sha384 = hash_utils::sha384(password);
+    login_hashed(auth_endpoint, email, &pass_sha256, &pass_sha384).await
+}
 
-    let endpoint = format!("{}/auth/login?email={}&pass_sha256={}", AUTH_ENDPOINT, email, pass_sha256);
+/// Re-derives the api key from the password hashes stored in the credential vault.
+pub async fn login_hashed(auth_endpoint: &str, email: &str, pass_sha256: &str, pass_sha384: &str) -> Result<String> {
+    let endpoint = format!("{}/auth/login?email={}&pass_sha256={}", auth_endpoint, email, pass_sha256);
 
     let res = reqwest::get(&endpoint)
         .await
-        .map_err(|e| anyhow!("error sending get request: {e}"))?;
+        .map_err(|e| TynkerError::from_reqwest(e, auth_endpoint))?;
     let res = validate_response(res).await?;
     
     let salt = res.text().await
-        .map_err(|e| anyhow!("error extracting test from response: {e}"))?;
+        .map_err(|e| TynkerError::Protocol(format!("error extracting text from response: {e}")))?;
 
-    if salt.contains("Incorrect password") {
-        return Err(anyhow!("Incorrect authentication"));
+    // The auth server reports bad credentials with a 200 response whose body is this message,
+    // possibly quoted or followed by a newline. 401s and 403s are already turned into
+    // `Unauthorized` by `validate_response`.
+    if salt.trim().trim_matches('"') == INCORRECT_PASSWORD {
+        return Err(TynkerError::Unauthorized("Incorrect email or password".to_string()));
     }
 
-    let pass_sha384 = hash_utils::sha384(password);
-    let api_key = tynkerbase_universal::crypt_utils::gen_apikey(&pass_sha384, &salt);
+    let api_key = tynkerbase_universal::crypt_utils::gen_apikey(pass_sha384, &salt);
 
     Ok(api_key)
 }
 
-pub async fn create_account(email: &str, password: &str) -> Result<()> {
+pub async fn create_account(auth_endpoint: &str, email: &str, password: &str) -> Result<()> {
     let pass_sha256 = hash_utils::sha256(password);
 
-    let endpoint = format!("{}/auth/create-account?email={}&pass_sha256={}", AUTH_ENDPOINT, email, pass_sha256);
+    let endpoint = format!("{}/auth/create-account?email={}&pass_sha256={}", auth_endpoint, email, pass_sha256);
 
     let res = reqwest::get(&endpoint)
         .await
-        .map_err(|e| anyhow!("error sending get request: {e}"))?;
+        .map_err(|e| TynkerError::from_reqwest(e, auth_endpoint))?;
 
     validate_response(res).await?;
     Ok(())
 }
 
-pub async fn delete_account(email: &str, password: &str) -> Result<()> {
+pub async fn delete_account(auth_endpoint: &str, email: &str, password: &str) -> Result<()> {
     let pass_sha256 = hash_utils::sha256(password);
 
-    let endpoint = format!("{}/auth/delete-account?email={}&pass_sha256={}", AUTH_ENDPOINT, email, pass_sha256);
+    let endpoint = format!("{}/auth/delete-account?email={}&pass_sha256={}", auth_endpoint, email, pass_sha256);
 
     let res = reqwest::get(&endpoint)
         .await
-        .map_err(|e| anyhow!("error sending get request: {e}"))?;
+        .map_err(|e| TynkerError::from_reqwest(e, auth_endpoint))?;
 
     validate_response(res).await?;
     Ok(())
 }
 
-pub async fn get_nodes(email: &str, pass_sha256: &str) -> Result<Vec<Node>>{
-    let endpoint = format!("{}/ngrok/get-all-addrs?email={}&pass_sha256={}", AUTH_ENDPOINT, email, pass_sha256);
+pub async fn get_nodes(auth_endpoint: &str, email: &str, pass_sha256: &str) -> Result<Vec<Node>>{
+    let endpoint = format!("{}/ngrok/get-all-addrs?email={}&pass_sha256={}", auth_endpoint, email, pass_sha256);
 
     let res = reqwest::get(&endpoint)
         .await
-        .map_err(|e| anyhow!("Failed to communicate with server -> {}", e))?;
+        .map_err(|e| TynkerError::from_reqwest(e, auth_endpoint))?;
 
     let res = validate_response(res).await?;
 
     let bin = res
         .bytes()
         .await
-        .map_err(|e| anyhow!("Failed to communicate with server -> {}", e))?
+        .map_err(|e| TynkerError::Protocol(format!("Failed to read response from server -> {}", e)))?
         .to_vec();
 
     bincode::deserialize(&bin)
-        .map_err(|e| anyhow!("Failed to deserialize response -> {}", e))
+        .map_err(|e| TynkerError::Protocol(format!("Failed to deserialize response -> {}", e)))
 }
 
-pub async fn remove_node(email: &str, pass_sha256: &str, node_id: &str) -> Result<()> {
-    let endpoint = format!("{}/ngrok/remove-addr?email={}&pass_sha256={}&node_id={}", AUTH_ENDPOINT, email, pass_sha256, node_id);
+pub async fn remove_node(auth_endpoint: &str, email: &str, pass_sha256: &str, node_id: &str) -> Result<()> {
+    let endpoint = format!("{}/ngrok/remove-addr?email={}&pass_sha256={}&node_id={}", auth_endpoint, email, pass_sha256, node_id);
     let res = reqwest::get(&endpoint)
         .await
-        .map_err(|e| anyhow!("Failed to communicate with server -> {}", e))?;
+        .map_err(|e| TynkerError::from_reqwest(e, auth_endpoint))?;
 
     validate_response(res).await?;
     Ok(())
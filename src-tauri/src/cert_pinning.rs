@@ -0,0 +1,101 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    ClientConfig,
+    DigitallySignedStruct,
+    SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+
+/// Trust on first use verifier for agent certificates. Agents use self signed certificates,
+/// so instead of validating a chain we compare the sha256 fingerprint of the leaf
+/// certificate against the one recorded the first time we connected to the node.
+#[derive(Debug)]
+pub struct PinnedCertVerifier {
+    pinned: Option<String>,
+    observed: Arc<Mutex<Option<String>>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedCertVerifier {
+    pub fn new(pinned: Option<String>, observed: Arc<Mutex<Option<String>>>) -> Self {
+        PinnedCertVerifier {
+            pinned,
+            observed,
+            provider: Arc::new(crypto::ring::default_provider()),
+        }
+    }
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = fingerprint(end_entity.as_ref());
+        *self.observed.lock().unwrap() = Some(fingerprint.clone());
+
+        match &self.pinned {
+            Some(pinned) if pinned != &fingerprint => {
+                Err(rustls::Error::General("certificate fingerprint does not match the pinned certificate".to_string()))
+            }
+            _ => Ok(ServerCertVerified::assertion()),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Builds a rustls config that only trusts `pinned` (or anything, recording it, if `None`).
+pub fn tls_config(pinned: Option<String>, observed: Arc<Mutex<Option<String>>>) -> Result<ClientConfig> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| anyhow!("Error configuring TLS -> {}", e))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier::new(pinned, observed)))
+        .with_no_client_auth();
+    Ok(config)
+}
+
+pub fn fingerprint(der: &[u8]) -> String {
+    hex::encode(Sha256::digest(der))
+}
+
+/// Formats a fingerprint as colon separated byte pairs for display.
+pub fn display_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .as_bytes()
+        .chunks(2)
+        .map(|c| String::from_utf8_lossy(c).to_uppercase())
+        .collect::<Vec<String>>()
+        .join(":")
+}
@@ -0,0 +1,657 @@
+use serde::{de::DeserializeOwned, Serialize, Deserialize};
+use serde_json::{Map, Value};
+use anyhow::{anyhow, Result};
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+use tynkerbase_universal::netwk_utils::ProjConfig;
+
+use crate::agent_interface::Resources;
+use crate::health::HealthCheck;
+
+/// Client only settings kept in `tynkerbase-config.json` next to the fields of `ProjConfig`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeployConfig {
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheck>,
+    /// Environment variables passed to the container. Secrets are kept out of this file,
+    /// see `secrets`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Resources::is_unlimited")]
+    pub resources: Resources,
+}
+
+/// How a release is rolled out across the project's upstream nodes.
+///
+/// ```json
+/// "strategy": { "type": "rolling", "batch_size": 2, "wait_secs": 30 }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Every node at once.
+    #[default]
+    AllAtOnce,
+    /// `batch_size` nodes at a time, each batch has to be healthy before the next one starts.
+    Rolling {
+        #[serde(default = "default_batch_size")]
+        batch_size: usize,
+        /// Seconds to wait after a batch comes up before running its health check.
+        #[serde(default)]
+        wait_secs: u64,
+    },
+    /// One node first, then the rest once the canary is healthy and the release is promoted.
+    Canary {
+        /// Promote to the remaining nodes without asking.
+        #[serde(default)]
+        auto_promote: bool,
+        #[serde(default)]
+        wait_secs: u64,
+    },
+}
+
+fn default_batch_size() -> usize {
+    1
+}
+
+impl Strategy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Strategy::AllAtOnce => "all-at-once",
+            Strategy::Rolling { .. } => "rolling",
+            Strategy::Canary { .. } => "canary",
+        }
+    }
+
+    /// Splits `nodes` nodes into the sizes of the batches they're deployed in.
+    pub fn batches(&self, nodes: usize) -> Vec<usize> {
+        if nodes == 0 {
+            return vec![];
+        }
+        match self {
+            Strategy::AllAtOnce => vec![nodes],
+            Strategy::Rolling { batch_size, .. } => {
+                let size = (*batch_size).max(1);
+                let mut batches = vec![size; nodes / size];
+                if nodes % size != 0 {
+                    batches.push(nodes % size);
+                }
+                batches
+            }
+            Strategy::Canary { .. } if nodes == 1 => vec![1],
+            Strategy::Canary { .. } => vec![1, nodes - 1],
+        }
+    }
+
+    pub fn wait_secs(&self) -> u64 {
+        match self {
+            Strategy::AllAtOnce => 0,
+            Strategy::Rolling { wait_secs, .. } | Strategy::Canary { wait_secs, .. } => *wait_secs,
+        }
+    }
+}
+
+/// Overlay of `path` for environment `env`, e.g. `tynkerbase-config.prod.json`.
+pub fn overlay_path(path: &str, env: &str) -> String {
+    match path.strip_suffix(".json") {
+        Some(stem) => format!("{}.{}.json", stem, env),
+        None => format!("{}.{}", path, env),
+    }
+}
+
+/// Environment names are joined into the overlay's file name, so they're limited to letters,
+/// numbers, `-` and `_`.
+pub fn validate_env_name(env: &str) -> Result<()> {
+    let valid = env.len() > 0 && env
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if !valid {
+        return Err(anyhow!("Environment name `{}` may only contain letters, numbers, `-` and `_`", env));
+    }
+    Ok(())
+}
+
+/// Reads the config at `path` and, if `env` is given, merges the environment's overlay over it.
+/// Objects are merged key by key, any other value in the overlay replaces the base one, so an
+/// overlay's `node_names` replaces the base list rather than adding to it.
+pub fn load_merged(path: &str, env: Option<&str>) -> Result<Value> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Error reading from file `{}` -> {}", path, e))?;
+    let mut value: Value = serde_json::from_str(&text)
+        .map_err(|e| anyhow!("Error parsing `{}` -> {}", path, e))?;
+
+    if let Some(env) = env {
+        validate_env_name(env)?;
+        let overlay_path = overlay_path(path, env);
+        if !Path::new(&overlay_path).exists() {
+            return Err(anyhow!("No config for environment `{}`, create `{}` to add one", env, overlay_path));
+        }
+        let text = fs::read_to_string(&overlay_path)
+            .map_err(|e| anyhow!("Error reading from file `{}` -> {}", &overlay_path, e))?;
+        let overlay: Value = serde_json::from_str(&text)
+            .map_err(|e| anyhow!("Error parsing `{}` -> {}", &overlay_path, e))?;
+        merge(&mut value, overlay);
+    }
+    Ok(value)
+}
+
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, v) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, v),
+                    None => { base.insert(key, v); }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// The project and deployment settings for environment `env` (or the base config if `None`).
+pub fn load_env(path: &str, env: Option<&str>) -> Result<(ProjConfig, DeployConfig)> {
+    let value = load_merged(path, env)?;
+    let conf = serde_json::from_value(value.clone())
+        .map_err(|e| anyhow!("Error parsing project config in `{}` -> {}", path, e))?;
+    let deploy_conf = serde_json::from_value(value)
+        .map_err(|e| anyhow!("Error parsing deployment settings in `{}` -> {}", path, e))?;
+    Ok((conf, deploy_conf))
+}
+
+/// Writes `conf` back to `path` while keeping the keys `ProjConfig` doesn't know about
+/// (such as the deployment strategy).
+pub fn save_proj_config(path: &str, conf: &ProjConfig) -> Result<()> {
+    let mut value = serde_json::to_value(conf)
+        .map_err(|e| anyhow!("Error serializing project config -> {}", e))?;
+
+    if Path::new(path).exists() {
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Error reading from file `{}` -> {}", path, e))?;
+        if let (Ok(Value::Object(existing)), Value::Object(updated)) = (serde_json::from_str::<Value>(&text), &mut value) {
+            for (key, v) in existing {
+                updated.entry(key).or_insert(v);
+            }
+        }
+    }
+
+    let text = serde_json::to_string_pretty(&value)
+        .map_err(|e| anyhow!("Error serializing project config -> {}", e))?;
+    fs::write(path, text)
+        .map_err(|e| anyhow!("Error writing to file `{}` -> {}", path, e))
+}
+
+/// Sets (or with `None`, removes) an environment variable in the project config.
+pub fn set_env(path: &str, key: &str, value: Option<&str>) -> Result<()> {
+    edit_raw(path, |conf| {
+        let env = conf
+            .entry("env")
+            .or_insert(Value::Object(Map::new()));
+        if !env.is_object() {
+            *env = Value::Object(Map::new());
+        }
+        let env = env.as_object_mut().unwrap();
+        match value {
+            Some(v) => { env.insert(key.to_string(), Value::String(v.to_string())); }
+            None => { env.remove(key); }
+        }
+        if env.is_empty() {
+            conf.remove("env");
+        }
+    })
+}
+
+/// Project names end up in file names, so only docker's characters are allowed and they can't
+/// start with a `.`.
+pub fn validate_project_name(name: &str) -> Result<()> {
+    let valid = !name.starts_with('.') && name.len() > 0 && name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+    if !valid {
+        return Err(anyhow!("Project name `{}` may only contain letters, numbers, `-`, `_` and `.`, and can't start with `.`", name));
+    }
+    Ok(())
+}
+
+/// Renames the project in the base config, leaving overlays and every other key alone.
+pub fn set_proj_name(path: &str, name: &str) -> Result<()> {
+    edit_raw(path, |conf| {
+        conf.insert("proj_name".to_string(), Value::String(name.to_string()));
+    })
+}
+
+/// Applies `f` to the config's json object and writes it back, leaving every other key as is.
+fn edit_raw(path: &str, f: impl FnOnce(&mut Map<String, Value>)) -> Result<()> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Error reading from file `{}` -> {}", path, e))?;
+    let mut value: Value = serde_json::from_str(&text)
+        .map_err(|e| anyhow!("Error parsing `{}` -> {}", path, e))?;
+    let conf = value
+        .as_object_mut()
+        .ok_or(anyhow!("`{}` must contain a json object", path))?;
+    f(conf);
+
+    let text = serde_json::to_string_pretty(&value)
+        .map_err(|e| anyhow!("Error serializing project config -> {}", e))?;
+    fs::write(path, text)
+        .map_err(|e| anyhow!("Error writing to file `{}` -> {}", path, e))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found while validating the project config.
+#[derive(Debug, Clone, Serialize)]
+pub struct Issue {
+    pub severity: Severity,
+    pub file: String,
+    /// Dotted path of the field, e.g. `strategy.batch_size`. Empty if the problem is with the whole file.
+    pub field: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}", severity, self.file)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if self.field.len() > 0 {
+            write!(f, " `{}`", self.field)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+const TOP_LEVEL_KEYS: &[&str] = &["proj_name", "node_names", "ignore", "strategy", "health_check", "env", "resources"];
+const STRATEGY_KEYS: &[&str] = &["type", "batch_size", "wait_secs", "auto_promote"];
+const HEALTH_CHECK_KEYS: &[&str] = &[
+    "type", "host", "port", "path", "expected_status", "secs", "timeout_secs", "interval_secs", "rollback_on_failure",
+];
+const RESOURCES_KEYS: &[&str] = &["cpus", "memory_mb"];
+
+/// Environments that have an overlay next to `path`, e.g. `prod` for `tynkerbase-config.prod.json`.
+pub fn list_envs(path: &str) -> Vec<String> {
+    let path = Path::new(path);
+    let dir = match path.parent() {
+        Some(d) if d.as_os_str().len() > 0 => d,
+        _ => Path::new("."),
+    };
+    let Some(stem) = path.file_stem().map(|s| s.to_string_lossy().to_string()) else {
+        return vec![];
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut envs = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            name.strip_prefix(&format!("{}.", stem))
+                .and_then(|n| n.strip_suffix(".json"))
+                .filter(|env| env.len() > 0)
+                .map(|env| env.to_string())
+        })
+        .collect::<Vec<String>>();
+    envs.sort();
+    envs
+}
+
+/// Checks the config at `path` (merged with `env`'s overlay, if given) for syntax errors, values
+/// of the wrong type and unknown keys. Referenced nodes are checked against `nodes` if given.
+pub fn validate(path: &str, env: Option<&str>, nodes: Option<&[String]>) -> Vec<Issue> {
+    let mut issues = vec![];
+    let mut files = vec![path.to_string()];
+    if let Some(env) = env {
+        if let Err(e) = validate_env_name(env) {
+            issues.push(Issue { severity: Severity::Error, file: path.to_string(), field: String::new(), line: None, message: e.to_string() });
+            return issues;
+        }
+        files.push(overlay_path(path, env));
+    }
+
+    let mut sources = vec![];
+    for file in files {
+        let text = match fs::read_to_string(&file) {
+            Ok(t) => t,
+            Err(e) => {
+                let message = if Path::new(&file).exists() {
+                    format!("unable to read file -> {}", e)
+                }
+                else {
+                    "file not found".to_string()
+                };
+                issues.push(Issue { severity: Severity::Error, file, field: String::new(), line: None, message });
+                return issues;
+            }
+        };
+        let value = match serde_json::from_str::<Value>(&text) {
+            Ok(v) => v,
+            Err(e) => {
+                // serde's message ends with the position, which is reported separately
+                let message = e.to_string();
+                let message = message.split(" at line ").next().unwrap_or(&message).to_string();
+                issues.push(Issue { severity: Severity::Error, file, field: String::new(), line: Some(e.line()), message });
+                return issues;
+            }
+        };
+        if !value.is_object() {
+            issues.push(Issue {
+                severity: Severity::Error,
+                file,
+                field: String::new(),
+                line: Some(1),
+                message: "must contain a json object".to_string(),
+            });
+            return issues;
+        }
+        unknown_keys(&file, &text, &value, &mut issues);
+        sources.push((file, text, value));
+    }
+
+    let mut merged = Value::Object(Map::new());
+    for (_, _, value) in sources.iter() {
+        merge(&mut merged, value.clone());
+    }
+    let merged = merged.as_object().unwrap();
+
+    // Reports against the last file that sets the field, since that's the value being used
+    let locate = |field: &str| -> (String, Option<usize>) {
+        for (file, text, _) in sources.iter().rev() {
+            if let Some(line) = find_line(text, field) {
+                return (file.clone(), Some(line));
+            }
+        }
+        (path.to_string(), None)
+    };
+    let mut error = |field: &str, message: String| {
+        let (file, line) = locate(field);
+        issues.push(Issue { severity: Severity::Error, file, field: field.to_string(), line, message });
+    };
+
+    let proj_name = check_field::<String>(merged, "proj_name", &mut error);
+    let node_names = check_field::<Vec<String>>(merged, "node_names", &mut error);
+    check_field::<Vec<String>>(merged, "ignore", &mut error);
+    let strategy = check_field::<Strategy>(merged, "strategy", &mut error);
+    let health_check = check_field::<HealthCheck>(merged, "health_check", &mut error);
+    let env_vars = check_field::<BTreeMap<String, String>>(merged, "env", &mut error);
+    let resources = check_field::<Resources>(merged, "resources", &mut error);
+
+    if !merged.contains_key("proj_name") {
+        error("proj_name", "missing, every project needs a name".to_string());
+    }
+    if let Some(Strategy::Rolling { batch_size: 0, .. }) = strategy {
+        error("strategy.batch_size", "must be at least 1".to_string());
+    }
+    if let Some(hc) = health_check {
+        if hc.interval_secs == 0 {
+            error("health_check.interval_secs", "must be at least 1".to_string());
+        }
+    }
+    if let Some(r) = resources {
+        if r.cpus.is_some_and(|c| c <= 0.) {
+            error("resources.cpus", "must be greater than 0".to_string());
+        }
+        if r.memory_mb == Some(0) {
+            error("resources.memory_mb", "must be greater than 0".to_string());
+        }
+    }
+    for key in env_vars.iter().flat_map(|e| e.keys()) {
+        if let Err(e) = crate::secrets::validate_key(key) {
+            error(&format!("env.{}", key), e.to_string());
+        }
+    }
+    if let (Some(node_names), Some(nodes)) = (&node_names, nodes) {
+        for name in node_names.iter() {
+            if !nodes.contains(name) {
+                error("node_names", format!("no node named `{}`, see `tyb list-nodes`", name));
+            }
+        }
+    }
+
+    let mut warning = |field: &str, message: String| {
+        let (file, line) = locate(field);
+        issues.push(Issue { severity: Severity::Warning, file, field: field.to_string(), line, message });
+    };
+    if let Some(name) = proj_name {
+        let mut conf = ProjConfig::default();
+        conf.proj_name = name;
+        if !conf.parse_name() {
+            warning("proj_name", format!("doesn't follow docker's naming conventions, it will be changed to `{}`", conf.proj_name));
+        }
+    }
+    if let Some(node_names) = node_names {
+        if node_names.len() == 0 {
+            warning("node_names", "no upstream nodes set, use `tyb add-upstream` to add one".to_string());
+        }
+        let mut seen = vec![];
+        for name in node_names {
+            if seen.contains(&name) {
+                warning("node_names", format!("node `{}` is listed more than once", name));
+            }
+            seen.push(name);
+        }
+    }
+
+    issues
+}
+
+/// Deserializes `key` on its own so errors name the field they're about.
+fn check_field<T: DeserializeOwned>(conf: &Map<String, Value>, key: &str, error: &mut impl FnMut(&str, String)) -> Option<T> {
+    let value = conf.get(key)?;
+    match serde_json::from_value(value.clone()) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            error(key, e.to_string());
+            None
+        }
+    }
+}
+
+fn unknown_keys(file: &str, text: &str, value: &Value, issues: &mut Vec<Issue>) {
+    let mut check = |prefix: &str, obj: &Map<String, Value>, known: &[&str]| {
+        for key in obj.keys() {
+            if known.contains(&key.as_str()) {
+                continue;
+            }
+            let field = if prefix.len() > 0 { format!("{}.{}", prefix, key) } else { key.clone() };
+            let mut message = "unknown key, it will be ignored".to_string();
+            if let Some(s) = known.iter().find(|k| edit_distance(k, key) <= 2) {
+                message = format!("unknown key, did you mean `{}`?", s);
+            }
+            issues.push(Issue {
+                severity: Severity::Warning,
+                file: file.to_string(),
+                line: find_line(text, &field),
+                field,
+                message,
+            });
+        }
+    };
+
+    let Some(conf) = value.as_object() else {
+        return;
+    };
+    check("", conf, TOP_LEVEL_KEYS);
+    for (key, known) in [("strategy", STRATEGY_KEYS), ("health_check", HEALTH_CHECK_KEYS), ("resources", RESOURCES_KEYS)] {
+        if let Some(Value::Object(obj)) = conf.get(key) {
+            check(key, obj, known);
+        }
+    }
+}
+
+/// Line of the key named by the dotted path `field`. Each segment is searched for after the
+/// previous one, which is close enough for the nesting the config uses.
+fn find_line(text: &str, field: &str) -> Option<usize> {
+    let mut pos = 0;
+    for segment in field.split('.') {
+        let quoted = format!("\"{}\"", segment);
+        let mut from = pos;
+        loop {
+            let i = from + text[from..].find(&quoted)?;
+            let after = text[i + quoted.len()..].trim_start();
+            from = i + quoted.len();
+            if after.starts_with(':') {
+                pos = from;
+                break;
+            }
+        }
+    }
+    Some(text[..pos].matches('\n').count() + 1)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut prev = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `files` into a temporary directory and returns it with the path of the base config.
+    fn project(files: &[(&str, &str)]) -> (tempfile::TempDir, String) {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, text) in files {
+            fs::write(dir.path().join(name), text).unwrap();
+        }
+        let path = dir.path().join("tynkerbase-config.json").to_string_lossy().to_string();
+        (dir, path)
+    }
+
+    const BASE: &str = r#"{
+  "proj_name": "web",
+  "node_names": ["a", "b"],
+  "strategy": {
+    "type": "rolling",
+    "batch_size": 2
+  }
+}"#;
+
+    #[test]
+    fn valid_config_has_no_issues() {
+        let (_dir, path) = project(&[("tynkerbase-config.json", BASE)]);
+        let issues = validate(&path, None, Some(&["a".to_string(), "b".to_string()]));
+        assert!(issues.is_empty(), "{:?}", issues);
+    }
+
+    #[test]
+    fn wrong_types_are_errors_on_their_line() {
+        let text = "{\n  \"proj_name\": \"web\",\n  \"node_names\": \"a\",\n  \"resources\": { \"cpus\": \"two\" }\n}";
+        let (_dir, path) = project(&[("tynkerbase-config.json", text)]);
+
+        let issues = validate(&path, None, None);
+        let errors = issues.iter().filter(|i| i.severity == Severity::Error).collect::<Vec<&Issue>>();
+        assert_eq!(errors.len(), 2, "{:?}", issues);
+        assert_eq!(errors[0].field, "node_names");
+        assert_eq!(errors[0].line, Some(3));
+        assert_eq!(errors[1].field, "resources");
+        assert_eq!(errors[1].line, Some(4));
+    }
+
+    #[test]
+    fn unknown_keys_suggest_close_matches() {
+        let text = "{\n  \"proj_name\": \"web\",\n  \"stratgy\": {},\n  \"resources\": { \"memory\": 512 },\n  \"colour\": 1\n}";
+        let (_dir, path) = project(&[("tynkerbase-config.json", text)]);
+
+        let issues = validate(&path, None, None);
+        assert!(issues.iter().all(|i| i.severity == Severity::Warning), "{:?}", issues);
+        let find = |field: &str| issues.iter().find(|i| i.field == field).expect(field);
+        assert_eq!(find("stratgy").message, "unknown key, did you mean `strategy`?");
+        assert_eq!(find("stratgy").line, Some(3));
+        assert_eq!(find("resources.memory").message, "unknown key, it will be ignored");
+        assert_eq!(find("resources.memory").line, Some(4));
+        assert_eq!(find("colour").message, "unknown key, it will be ignored");
+    }
+
+    #[test]
+    fn syntax_errors_report_their_line() {
+        let (_dir, path) = project(&[("tynkerbase-config.json", "{\n  \"proj_name\": \"web\"\n  \"node_names\": []\n}")]);
+
+        let issues = validate(&path, None, None);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Error);
+        assert_eq!(issues[0].line, Some(3));
+    }
+
+    #[test]
+    fn find_line_follows_nested_fields() {
+        assert_eq!(find_line(BASE, "proj_name"), Some(2));
+        assert_eq!(find_line(BASE, "strategy.batch_size"), Some(6));
+        assert_eq!(find_line(BASE, "strategy.wait_secs"), None);
+        // A value equal to a key's name isn't the key
+        assert_eq!(find_line("{\n  \"a\": \"env\",\n  \"env\": {}\n}", "env"), Some(3));
+    }
+
+    #[test]
+    fn edit_distance_counts_single_character_edits() {
+        assert_eq!(edit_distance("strategy", "strategy"), 0);
+        assert_eq!(edit_distance("strategy", "stratgy"), 1);
+        assert_eq!(edit_distance("node_names", "node_name"), 1);
+        assert_eq!(edit_distance("port", "path"), 3);
+        assert_eq!(edit_distance("", "env"), 3);
+    }
+
+    #[test]
+    fn overlays_replace_values_and_merge_objects() {
+        let overlay = r#"{ "node_names": ["c"], "strategy": { "batch_size": 1 } }"#;
+        let (_dir, path) = project(&[("tynkerbase-config.json", BASE), ("tynkerbase-config.prod.json", overlay)]);
+
+        let merged = load_merged(&path, Some("prod")).unwrap();
+        assert_eq!(merged["proj_name"], "web");
+        assert_eq!(merged["node_names"], serde_json::json!(["c"]));
+        assert_eq!(merged["strategy"]["type"], "rolling");
+        assert_eq!(merged["strategy"]["batch_size"], 1);
+
+        assert!(load_merged(&path, Some("staging")).is_err());
+        assert_eq!(list_envs(&path), vec!["prod"]);
+    }
+
+    #[test]
+    fn issues_are_reported_against_the_overlay_that_sets_them() {
+        let overlay = "{\n  \"strategy\": {\n    \"batch_size\": 0\n  }\n}";
+        let (_dir, path) = project(&[("tynkerbase-config.json", BASE), ("tynkerbase-config.prod.json", overlay)]);
+
+        let issues = validate(&path, Some("prod"), None);
+        assert_eq!(issues.len(), 1, "{:?}", issues);
+        assert_eq!(issues[0].field, "strategy.batch_size");
+        assert_eq!(issues[0].file, overlay_path(&path, "prod"));
+        assert_eq!(issues[0].line, Some(3));
+
+        let issues = validate(&path, Some("staging"), None);
+        assert_eq!(issues[0].message, "file not found");
+    }
+
+    #[test]
+    fn env_names_cant_leave_the_project_directory() {
+        let (_dir, path) = project(&[("tynkerbase-config.json", BASE)]);
+        assert!(validate_env_name("prod-eu_1").is_ok());
+        for env in ["../prod", "..", "a/b", "a\\b", ".hidden", ""] {
+            assert!(validate_env_name(env).is_err(), "`{}` should be rejected", env);
+            assert!(load_merged(&path, Some(env)).is_err(), "`{}` should be rejected", env);
+            let issues = validate(&path, Some(env), None);
+            assert!(issues.iter().any(|i| i.severity == Severity::Error), "`{}` should be rejected", env);
+        }
+    }
+}
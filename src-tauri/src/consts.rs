@@ -23,6 +23,15 @@ pub fn app_data() -> String {
     panic!("could not find home dir");
 }
 
-pub const AUTH_ENDPOINT: &str = "https://tynkerbase-server.shuttleapp.rs";
+pub const DEFAULT_AUTH_ENDPOINT: &str = "https://tynkerbase-server.shuttleapp.rs";
+pub const AUTH_ENDPOINT_ENV: &str = "TYB_AUTH_ENDPOINT";
+/// Api key to send to the nodes instead of the one saved for the profile, for CI.
+pub const API_KEY_ENV: &str = "TYB_API_KEY";
+/// Email to use with `TYB_API_KEY` when no profile is logged in.
+pub const EMAIL_ENV: &str = "TYB_EMAIL";
+/// Comma separated nodes, `addr` or `name=addr`, to use with `TYB_API_KEY` when no profile is logged in.
+pub const NODES_ENV: &str = "TYB_NODES";
 pub const PROJ_JSON_CONFIG: &str = "tynkerbase-config.json";
-pub const NG_SKIP_WARN: &str = "ngrok-skip-browser-warning";
\ No newline at end of file
+pub const NG_SKIP_WARN: &str = "ngrok-skip-browser-warning";
+/// Last line of a streamed build log, followed by `ok` or `failed`.
+pub const BUILD_STATUS_PREFIX: &str = "[tyb-build-status]";
\ No newline at end of file
@@ -0,0 +1,390 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use tynkerbase_universal::{
+    file_utils::FileCollection,
+    netwk_utils::ProjConfig,
+};
+
+use crate::agent_interface::{self, AgentClient, Release, SpawnOptions, SyncSummary};
+use crate::config::{DeployConfig, Strategy};
+use crate::error::{Result, TynkerError};
+use crate::file_sync::{self, Manifest};
+use crate::global_state::GlobalState;
+use crate::health::{self, HealthCheck};
+use crate::history::{DeployRecord, Step, StepOutcome};
+use crate::secrets;
+
+/// Receives progress while a deployment runs. Every method does nothing by default, so
+/// implementations only need the ones they display.
+pub trait Reporter: Send + Sync {
+    fn batch_started(&self, _index: usize, _total: usize, _nodes: &[String]) {}
+    /// A step is starting on every node of the current batch.
+    fn step_started(&self, _step: Step) {}
+    fn upload_progress(&self, _node: &str, _sent: u64, _total: u64) {}
+    fn synced(&self, _node: &str, _summary: &SyncSummary) {}
+    fn build_log(&self, _node: &str, _line: &str) {}
+    fn waiting(&self, _secs: u64) {}
+    fn checking_health(&self, _check: &HealthCheck) {}
+    fn healthy(&self, _node: &str) {}
+    fn failed(&self, _node: &str, _step: Step, _error: &TynkerError) {}
+    fn rolled_back(&self, _node: &str, _version: &str) {}
+    /// The batch failed and the deployment stopped with `remaining` nodes left untouched.
+    fn halted(&self, _index: usize, _remaining: usize) {}
+    /// Asked once the canary is healthy, unless the strategy promotes automatically.
+    /// Returning `false` leaves the remaining nodes on their previous release.
+    fn promote(&self, _canary: &str, _version: &str, _remaining: usize) -> bool {
+        false
+    }
+}
+
+/// A `Reporter` that ignores everything and never promotes canaries.
+pub struct Silent;
+
+impl Reporter for Silent {}
+
+/// What happened to a deployment.
+#[derive(Debug, Clone)]
+pub struct DeployReport {
+    pub record: DeployRecord,
+    /// One message per failed step, in the order they happened.
+    pub failures: Vec<String>,
+}
+
+impl DeployReport {
+    pub fn succeeded(&self) -> bool {
+        self.record.status() == "succeeded"
+    }
+}
+
+/// A release of a project on its way to a set of nodes.
+///
+/// ```ignore
+/// let report = Deployment::new(conf, files, clients)
+///     .with_settings(deploy_conf)
+///     .with_author(&gstate.email, &gstate.profile)
+///     .run(Arc::new(Silent))
+///     .await;
+/// history::record(&report.record)?;
+/// ```
+pub struct Deployment {
+    conf: ProjConfig,
+    files: Arc<FileCollection>,
+    manifest: Arc<Manifest>,
+    clients: Vec<AgentClient>,
+    settings: DeployConfig,
+    spawn_opts: SpawnOptions,
+    version: String,
+    record: DeployRecord,
+}
+
+impl Deployment {
+    pub fn new(conf: ProjConfig, files: FileCollection, clients: Vec<AgentClient>) -> Self {
+        let version = agent_interface::new_release_version();
+        let manifest = file_sync::manifest(&files);
+        let nodes = clients.iter().map(|c| c.node().name.clone()).collect::<Vec<String>>();
+        let record = DeployRecord::new(&conf.proj_name, &version, "", "", &file_sync::manifest_hash(&manifest), &nodes);
+
+        Deployment {
+            conf,
+            files: Arc::new(files),
+            manifest: Arc::new(manifest),
+            clients,
+            settings: DeployConfig::default(),
+            spawn_opts: SpawnOptions::default(),
+            version,
+            record,
+        }
+    }
+
+    /// Strategy and health check to deploy with. The environment variables and resource limits
+    /// are taken from here as well, unless `with_spawn_options` is used afterwards.
+    pub fn with_settings(mut self, settings: DeployConfig) -> Self {
+        self.spawn_opts = SpawnOptions {
+            env: settings.env.clone(),
+            resources: settings.resources.clone(),
+        };
+        self.settings = settings;
+        self
+    }
+
+    pub fn with_spawn_options(mut self, spawn_opts: SpawnOptions) -> Self {
+        self.spawn_opts = spawn_opts;
+        self
+    }
+
+    /// Who deployed the release, for the history record.
+    pub fn with_author(mut self, email: &str, profile: &str) -> Self {
+        self.record.deployed_by = email.to_string();
+        self.record.profile = profile.to_string();
+        self
+    }
+
+    pub fn with_environment(mut self, environment: Option<String>) -> Self {
+        self.record.environment = environment;
+        self
+    }
+
+    /// Release version the deployment creates.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Size of the project's files in bytes.
+    pub fn size(&self) -> u64 {
+        self.files.sizeof() as u64
+    }
+
+    /// Rolls the release out batch by batch as the strategy dictates, stopping at the first
+    /// batch that fails. The returned record isn't saved, use `history::record` for that.
+    pub async fn run(self, reporter: Arc<dyn Reporter>) -> DeployReport {
+        let Deployment { conf, files, manifest, clients, settings, spawn_opts, version, record } = self;
+        let mut rollout = Rollout { conf, version, spawn_opts, files, manifest, reporter, record, failures: vec![] };
+        let reporter = rollout.reporter.clone();
+
+        let strategy = settings.strategy.clone();
+        // Staged rollouts at least need the containers to be up before moving on
+        let health_check = settings.health_check.clone().or_else(|| {
+            (strategy != Strategy::AllAtOnce).then(|| HealthCheck::running(0))
+        });
+        let batches = strategy.batches(clients.len());
+
+        let mut remaining = clients.into_iter();
+        for (i, &size) in batches.iter().enumerate() {
+            let batch = remaining.by_ref().take(size).collect::<Vec<AgentClient>>();
+            let names = batch.iter().map(|c| c.node().name.clone()).collect::<Vec<String>>();
+            reporter.batch_started(i, batches.len(), &names);
+
+            let deployed = rollout.deploy_batch(batch.clone()).await;
+            let mut healthy = deployed.len() == batch.len();
+            if let Some(ref check) = health_check {
+                if deployed.len() > 0 {
+                    let passed = rollout.batch_healthy(&deployed, check, strategy.wait_secs()).await;
+                    healthy = healthy && passed;
+                }
+            }
+
+            let left = remaining.len();
+            if !healthy {
+                if left > 0 {
+                    reporter.halted(i, left);
+                }
+                break;
+            }
+
+            if let Strategy::Canary { auto_promote: false, .. } = strategy {
+                if i == 0 && left > 0 && !reporter.promote(&names[0], &rollout.version, left) {
+                    break;
+                }
+            }
+        }
+
+        DeployReport { record: rollout.record, failures: rollout.failures }
+    }
+}
+
+/// Clients for the project's upstream nodes, along with a warning for each node that's
+/// missing from the account or that a client couldn't be created for.
+pub fn upstream_clients(gstate: &GlobalState, conf: &ProjConfig) -> (Vec<AgentClient>, Vec<String>) {
+    let mut clients = vec![];
+    let mut warnings = vec![];
+    for name in conf.node_names.iter() {
+        let Some(node) = gstate.nodes.iter().find(|n| &n.name == name) else {
+            warnings.push(format!("no upstream node found for node name `{}`", name));
+            continue;
+        };
+        match gstate.agent_client(node) {
+            Ok(c) => clients.push(c),
+            Err(e) => warnings.push(format!("unable to create a client for node `{}` -> {}", name, e)),
+        }
+    }
+    (clients, warnings)
+}
+
+/// The environment variables and resource limits for the project's containers. Secrets take
+/// precedence over plain variables with the same name.
+pub fn spawn_options(conf: &ProjConfig, settings: &DeployConfig) -> anyhow::Result<SpawnOptions> {
+    let mut env = settings.env.clone();
+    let project_secrets = secrets::load(&conf.proj_name)
+        .map_err(|e| anyhow!("Error loading secrets for project `{}` -> {}", &conf.proj_name, e))?;
+    env.extend(project_secrets);
+
+    Ok(SpawnOptions { env, resources: settings.resources.clone() })
+}
+
+/// Returns the newest release that is older than the active one.
+pub fn previous_release(releases: &[Release]) -> Option<String> {
+    let active = releases.iter().position(|r| r.active)?;
+    releases[..active].last().map(|r| r.version.clone())
+}
+
+/// Restores the release before the active one, returning the version it rolled back to.
+pub async fn rollback_to_previous(client: &AgentClient, proj_name: &str) -> Result<String> {
+    let releases = client.list_releases(proj_name).await?;
+    let version = previous_release(&releases)
+        .ok_or(TynkerError::Other("no release older than the current one".to_string()))?;
+    client.rollback(proj_name, &version).await?;
+    Ok(version)
+}
+
+/// State of a deployment shared by its batches: what's being deployed and what happened so far.
+struct Rollout {
+    conf: ProjConfig,
+    version: String,
+    spawn_opts: SpawnOptions,
+    files: Arc<FileCollection>,
+    manifest: Arc<Manifest>,
+    reporter: Arc<dyn Reporter>,
+    record: DeployRecord,
+    failures: Vec<String>,
+}
+
+impl Rollout {
+    /// Transfers, builds and spawns the release on every node in `clients`. Returns the
+    /// clients of the nodes that made it through every step.
+    async fn deploy_batch(&mut self, mut clients: Vec<AgentClient>) -> Vec<AgentClient> {
+        self.reporter.step_started(Step::Transfer);
+        let mut handles = vec![];
+        for c in clients.iter() {
+            let client = c.clone();
+            let files = self.files.clone();
+            let manifest = self.manifest.clone();
+            let proj_name = self.conf.proj_name.clone();
+            let reporter = self.reporter.clone();
+
+            let handle = tokio::spawn(async move {
+                let node_name = client.node().name.clone();
+                client.sync_proj(&proj_name, &files, &manifest, &mut |sent, total| {
+                    reporter.upload_progress(&node_name, sent, total);
+                }).await
+            });
+            handles.push((handle, c.node().clone()));
+        }
+
+        let mut failed_nodes = vec![];
+        for (handle, node) in handles {
+            let res = handle.await
+                .unwrap_or_else(|e| Err(TynkerError::Other(format!("Transfer task failed -> {}", e))));
+            match res {
+                Ok(s) => {
+                    self.record.set_outcome(&node.name, Step::Transfer, StepOutcome::Succeeded);
+                    self.reporter.synced(&node.name, &s);
+                }
+                Err(e) => {
+                    self.record.set_outcome(&node.name, Step::Transfer, StepOutcome::Failed(e.to_string()));
+                    self.reporter.failed(&node.name, Step::Transfer, &e);
+                    self.failures.push(format!("Failed to transfer files to node `{}` -> {}", &node.name, e.report()));
+                    failed_nodes.push(node.node_id.clone());
+                }
+            }
+        }
+        clients.retain(|c| !failed_nodes.contains(&c.node().node_id));
+
+        // Build on every node at once, streaming the logs as they come in
+        if clients.len() > 0 {
+            self.reporter.step_started(Step::Build);
+        }
+        let mut handles = vec![];
+        for c in clients.iter() {
+            let client = c.clone();
+            let proj_name = self.conf.proj_name.clone();
+            let version = self.version.clone();
+            let reporter = self.reporter.clone();
+            let handle = tokio::spawn(async move {
+                let node_name = client.node().name.clone();
+                client.build_img_streaming(&proj_name, &version, |line| {
+                    reporter.build_log(&node_name, line);
+                }).await
+            });
+            handles.push((handle, c.node().clone()));
+        }
+
+        let mut failed_nodes = vec![];
+        for (handle, node) in handles {
+            let res = handle.await
+                .unwrap_or_else(|e| Err(TynkerError::Other(format!("Build task failed -> {}", e))));
+            match res {
+                Ok(_) => self.record.set_outcome(&node.name, Step::Build, StepOutcome::Succeeded),
+                Err(e) => {
+                    self.record.set_outcome(&node.name, Step::Build, StepOutcome::Failed(e.to_string()));
+                    self.reporter.failed(&node.name, Step::Build, &e);
+                    self.failures.push(format!("Failed to build image on node `{}` -> {}", node.name, e.report()));
+                    failed_nodes.push(node.node_id.clone());
+                }
+            }
+        }
+        clients.retain(|c| !failed_nodes.contains(&c.node().node_id));
+
+        if clients.len() > 0 {
+            self.reporter.step_started(Step::Spawn);
+        }
+        let mut failed_nodes = vec![];
+        for c in clients.iter() {
+            let node = c.node();
+            match c.spawn_container(&self.conf, &self.version, &self.spawn_opts).await {
+                Ok(_) => self.record.set_outcome(&node.name, Step::Spawn, StepOutcome::Succeeded),
+                Err(e) => {
+                    self.record.set_outcome(&node.name, Step::Spawn, StepOutcome::Failed(e.to_string()));
+                    self.reporter.failed(&node.name, Step::Spawn, &e);
+                    self.failures.push(format!("Failed to spawn container on node `{}` -> {}", node.name, e.report()));
+                    failed_nodes.push(node.node_id.clone());
+                }
+            }
+        }
+        clients.retain(|c| !failed_nodes.contains(&c.node().node_id));
+        clients
+    }
+
+    /// Waits `wait_secs` and then runs the health check on every node at once, rolling
+    /// unhealthy nodes back if the check asks for it. Returns whether every node is healthy.
+    async fn batch_healthy(&mut self, clients: &[AgentClient], check: &HealthCheck, wait_secs: u64) -> bool {
+        if wait_secs > 0 {
+            self.reporter.waiting(wait_secs);
+            tokio::time::sleep(Duration::from_secs(wait_secs)).await;
+        }
+        self.reporter.step_started(Step::Health);
+        self.reporter.checking_health(check);
+
+        let mut handles = vec![];
+        for c in clients.iter() {
+            let client = c.clone();
+            let check = check.clone();
+            let proj_name = self.conf.proj_name.clone();
+            let handle = tokio::spawn(async move {
+                health::check(&client, &proj_name, &check).await
+            });
+            handles.push((handle, c));
+        }
+
+        let mut healthy = true;
+        for (handle, client) in handles {
+            let node = client.node();
+            let res = handle.await
+                .unwrap_or_else(|e| Err(TynkerError::Other(format!("Health check task failed -> {}", e))));
+            let err = match res {
+                Ok(_) => {
+                    self.record.set_outcome(&node.name, Step::Health, StepOutcome::Succeeded);
+                    self.reporter.healthy(&node.name);
+                    continue;
+                }
+                Err(e) => e,
+            };
+            healthy = false;
+            self.record.set_outcome(&node.name, Step::Health, StepOutcome::Failed(err.to_string()));
+            self.reporter.failed(&node.name, Step::Health, &err);
+            self.failures.push(format!("Node `{}` is unhealthy after deploying -> {}", node.name, err.report()));
+
+            if !check.rollback_on_failure {
+                continue;
+            }
+            match rollback_to_previous(client, &self.conf.proj_name).await {
+                Ok(v) => {
+                    self.record.set_rolled_back(&node.name, &v);
+                    self.reporter.rolled_back(&node.name, &v);
+                }
+                Err(e) => self.failures.push(format!("Failed to roll back unhealthy node `{}` -> {}", node.name, e.report())),
+            }
+        }
+        healthy
+    }
+}
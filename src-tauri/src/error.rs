@@ -0,0 +1,151 @@
+use std::fmt;
+
+use serde::{Serialize, Serializer, ser::SerializeStruct};
+
+use crate::cert_pinning;
+
+pub type Result<T> = std::result::Result<T, TynkerError>;
+
+/// Errors returned when talking to agents or the auth server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TynkerError {
+    /// The credentials or api key were rejected.
+    Unauthorized(String),
+    /// No connection could be made to the node or server.
+    Unreachable { target: String, reason: String },
+    /// The request didn't complete within its timeout.
+    Timeout { target: String },
+    /// The node presented a certificate other than the pinned one.
+    CertificateMismatch { node: String, pinned: String, observed: String },
+    /// The agent failed to build the project's docker image.
+    BuildFailed { project: String, output: String },
+    /// The container was spawned but didn't pass its health check.
+    Unhealthy { node: String, reason: String },
+    ProjectNotFound(String),
+    /// The account, or `TYB_NODES` without one, has no nodes to use.
+    NoNodes,
+    /// Any other non 2xx response.
+    Http { status: u16, body: String },
+    /// The response couldn't be read or deserialized.
+    Protocol(String),
+    InvalidEndpoint(String),
+    Other(String),
+    /// The request failed with `source` after being retried.
+    Retried { attempts: u32, source: Box<TynkerError> },
+}
+
+impl TynkerError {
+    /// Stable identifier for the error kind, sent to the frontend.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TynkerError::Unauthorized(_) => "unauthorized",
+            TynkerError::Unreachable { .. } => "unreachable",
+            TynkerError::Timeout { .. } => "timeout",
+            TynkerError::CertificateMismatch { .. } => "certificate_mismatch",
+            TynkerError::BuildFailed { .. } => "build_failed",
+            TynkerError::Unhealthy { .. } => "unhealthy",
+            TynkerError::ProjectNotFound(_) => "project_not_found",
+            TynkerError::NoNodes => "no_nodes",
+            TynkerError::Http { .. } => "http_error",
+            TynkerError::Protocol(_) => "protocol_error",
+            TynkerError::InvalidEndpoint(_) => "invalid_endpoint",
+            TynkerError::Other(_) => "other",
+            TynkerError::Retried { source, .. } => source.code(),
+        }
+    }
+
+    /// What the user can do about the error, if anything.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            TynkerError::Unauthorized(_) => Some("Log in again with `tyb logout` followed by `tyb login`."),
+            TynkerError::Unreachable { .. } => Some("Check that the node is online with `tyb list-nodes`."),
+            TynkerError::Timeout { .. } => Some("The node may be overloaded or behind a slow tunnel, try again."),
+            TynkerError::CertificateMismatch { .. } => Some("If the node was reinstalled, run `tyb node trust <name>`."),
+            TynkerError::BuildFailed { .. } => Some("Check that the Dockerfile builds locally with `docker build .`."),
+            TynkerError::Unhealthy { .. } => Some("Check that the `health_check` in tynkerbase-config.json matches what the container serves."),
+            TynkerError::ProjectNotFound(_) => Some("Deploy the project first with `tyb deploy`."),
+            TynkerError::NoNodes => Some("Install the agent on a machine to register it, or list nodes in `TYB_NODES`."),
+            TynkerError::Protocol(_) => Some("The node's agent may be out of date with this client."),
+            TynkerError::Retried { source, .. } => source.hint(),
+            _ => None,
+        }
+    }
+
+    /// The error message followed by the hint, for printing in the CLI.
+    pub fn report(&self) -> String {
+        match self.hint() {
+            Some(hint) => format!("{}\n    hint: {}", self, hint),
+            None => self.to_string(),
+        }
+    }
+
+    /// Classifies a reqwest error from a request made to `target`.
+    pub fn from_reqwest(err: reqwest::Error, target: &str) -> Self {
+        if err.is_timeout() {
+            return TynkerError::Timeout { target: target.to_string() };
+        }
+        if err.is_connect() || err.is_request() {
+            return TynkerError::Unreachable { target: target.to_string(), reason: err.to_string() };
+        }
+        if err.is_decode() || err.is_body() {
+            return TynkerError::Protocol(err.to_string());
+        }
+        TynkerError::Other(err.to_string())
+    }
+}
+
+impl fmt::Display for TynkerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TynkerError::Unauthorized(msg) => write!(f, "Unauthorized: {}", msg),
+            TynkerError::Unreachable { target, reason } => write!(f, "Unable to reach `{}`: {}", target, reason),
+            TynkerError::Timeout { target } => write!(f, "Request to `{}` timed out", target),
+            TynkerError::CertificateMismatch { node, pinned, observed } => write!(
+                f,
+                "\n@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@\n\
+                WARNING: THE CERTIFICATE OF NODE `{}` HAS CHANGED!\n\
+                @@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@\n\
+                Someone could be intercepting your connection, no requests were sent.\n\
+                Pinned fingerprint:   {}\n\
+                Received fingerprint: {}\n",
+                node,
+                cert_pinning::display_fingerprint(pinned),
+                cert_pinning::display_fingerprint(observed),
+            ),
+            TynkerError::BuildFailed { project, output } => write!(f, "Failed to build image for `{}`:\n{}", project, output),
+            TynkerError::Unhealthy { node, reason } => write!(f, "Node `{}` failed its health check: {}", node, reason),
+            TynkerError::ProjectNotFound(name) => write!(f, "Project `{}` does not exist on the node", name),
+            TynkerError::NoNodes => write!(f, "No nodes registered"),
+            TynkerError::Http { status, body } => write!(f, "\nNon 200 response from node\nStatus Code: {}\nText Body: {}\n", status, body),
+            TynkerError::Protocol(msg) => write!(f, "Unexpected response: {}", msg),
+            TynkerError::InvalidEndpoint(msg) => write!(f, "Invalid endpoint: {}", msg),
+            TynkerError::Other(msg) => write!(f, "{}", msg),
+            TynkerError::Retried { attempts, source } => write!(f, "{} (gave up after {} attempts)", source, attempts),
+        }
+    }
+}
+
+impl std::error::Error for TynkerError {}
+
+/// Serialized as `{ "code": ..., "message": ..., "hint": ... }` for the frontend.
+impl Serialize for TynkerError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("TynkerError", 3)?;
+        s.serialize_field("code", self.code())?;
+        s.serialize_field("message", &self.to_string())?;
+        s.serialize_field("hint", &self.hint())?;
+        s.end()
+    }
+}
+
+impl From<bincode::Error> for TynkerError {
+    fn from(err: bincode::Error) -> Self {
+        TynkerError::Protocol(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for TynkerError {
+    fn from(err: serde_json::Error) -> Self {
+        TynkerError::Protocol(err.to_string())
+    }
+}
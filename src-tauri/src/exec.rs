@@ -0,0 +1,142 @@
+use futures_util::{SinkExt, StreamExt};
+use serde::{Serialize, Deserialize};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::mpsc,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::agent_interface::AgentClient;
+use crate::error::{Result, TynkerError};
+
+const STDOUT: u8 = 1;
+const STDERR: u8 = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Control {
+    /// The local terminal changed size (tty only).
+    Resize { cols: u16, rows: u16 },
+    /// Local stdin was closed.
+    Eof,
+    /// The process exited, sent by the agent as the last message.
+    Exit { code: i32 },
+}
+
+/// Puts the terminal in raw mode so keystrokes (including ctrl-c) go to the remote process,
+/// restoring it when dropped.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> Result<Self> {
+        crossterm::terminal::enable_raw_mode()
+            .map_err(|e| TynkerError::Other(format!("Unable to put the terminal in raw mode -> {}", e)))?;
+        Ok(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        crossterm::terminal::disable_raw_mode().ok();
+    }
+}
+
+/// Runs `cmd` in the project's container, connecting it to this process's stdin and stdout.
+/// Returns the exit code of the remote process.
+///
+/// Binary frames sent to the agent are the process's stdin. Binary frames from the agent are
+/// its output, with the first byte naming the stream (`1` stdout, `2` stderr). Text frames
+/// carry `Control` messages as json.
+pub async fn run(client: &AgentClient, name: &str, cmd: &[String], tty: bool) -> Result<i32> {
+    let size = if tty { crossterm::terminal::size().ok() } else { None };
+    let socket = client.exec_socket(name, cmd, tty, size).await?;
+    let (mut sink, mut stream) = socket.split();
+
+    let _raw = if tty { Some(RawMode::enable()?) } else { None };
+
+    let (tx, mut rx) = mpsc::channel::<Message>(32);
+
+    let stdin_tx = tx.clone();
+    let stdin_task = tokio::spawn(async move {
+        let mut stdin = tokio::io::stdin();
+        let mut buf = [0u8; 4096];
+        loop {
+            match stdin.read(&mut buf).await {
+                Ok(0) | Err(_) => {
+                    let eof = serde_json::to_string(&Control::Eof).unwrap();
+                    stdin_tx.send(Message::Text(eof)).await.ok();
+                    break;
+                }
+                Ok(n) => {
+                    if stdin_tx.send(Message::Binary(buf[..n].to_vec())).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    // Polled rather than watched for signals, so it also works on windows
+    let resize_task = tokio::spawn(async move {
+        let mut last = size;
+        while tty {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            let current = crossterm::terminal::size().ok();
+            if current != last {
+                if let Some((cols, rows)) = current {
+                    let msg = serde_json::to_string(&Control::Resize { cols, rows }).unwrap();
+                    if tx.send(Message::Text(msg)).await.is_err() {
+                        break;
+                    }
+                }
+                last = current;
+            }
+        }
+    });
+
+    let mut stdout = tokio::io::stdout();
+    let mut stderr = tokio::io::stderr();
+    let mut exit_code = None;
+
+    let res: Result<()> = async {
+        loop {
+            tokio::select! {
+                Some(msg) = rx.recv() => {
+                    sink.send(msg).await
+                        .map_err(|e| TynkerError::Protocol(format!("Error sending to exec session -> {}", e)))?;
+                }
+                frame = stream.next() => match frame {
+                    None | Some(Ok(Message::Close(_))) => break,
+                    Some(Err(e)) => return Err(TynkerError::Protocol(format!("Error reading from exec session -> {}", e))),
+                    Some(Ok(Message::Binary(data))) => match data.split_first() {
+                        Some((&STDOUT, out)) => {
+                            stdout.write_all(out).await.ok();
+                            stdout.flush().await.ok();
+                        }
+                        Some((&STDERR, out)) => {
+                            stderr.write_all(out).await.ok();
+                            stderr.flush().await.ok();
+                        }
+                        _ => {}
+                    },
+                    Some(Ok(Message::Text(text))) => {
+                        if let Ok(Control::Exit { code }) = serde_json::from_str(&text) {
+                            exit_code = Some(code);
+                            break;
+                        }
+                    }
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+        Ok(())
+    }.await;
+
+    stdin_task.abort();
+    resize_task.abort();
+    sink.close().await.ok();
+    res?;
+
+    exit_code.ok_or(TynkerError::Protocol("exec session ended without an exit code, the connection may have dropped".to_string()))
+}
@@ -0,0 +1,139 @@
+use std::collections::{HashMap, HashSet};
+
+use sha2::{Digest, Sha256};
+use tynkerbase_universal::file_utils::FileCollection;
+
+/// Maps each file path in a project to the sha256 hash of its contents.
+pub type Manifest = HashMap<String, String>;
+
+/// Which files need to change on a node to match the local project.
+#[derive(Debug, Clone, Default)]
+pub struct SyncPlan {
+    /// New or modified files.
+    pub upload: Vec<String>,
+    /// Files on the node that no longer exist locally.
+    pub delete: Vec<String>,
+    pub unchanged: usize,
+}
+
+pub fn manifest(files: &FileCollection) -> Manifest {
+    files.files
+        .iter()
+        .zip(files.contents.iter())
+        .map(|(path, contents)| (path.clone(), hex::encode(Sha256::digest(contents))))
+        .collect()
+}
+
+/// Hash of the whole manifest, identifies the exact set of files that were deployed.
+pub fn manifest_hash(manifest: &Manifest) -> String {
+    let mut entries = manifest.iter().collect::<Vec<_>>();
+    entries.sort();
+
+    let mut hasher = Sha256::new();
+    for (path, hash) in entries {
+        hasher.update(path.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(hash.as_bytes());
+        hasher.update(b"\n");
+    }
+    hex::encode(hasher.finalize())
+}
+
+pub fn plan(local: &Manifest, remote: &Manifest) -> SyncPlan {
+    let mut plan = SyncPlan::default();
+
+    for (path, hash) in local.iter() {
+        if remote.get(path) == Some(hash) {
+            plan.unchanged += 1;
+        }
+        else {
+            plan.upload.push(path.clone());
+        }
+    }
+    for path in remote.keys() {
+        if !local.contains_key(path) {
+            plan.delete.push(path.clone());
+        }
+    }
+
+    plan.upload.sort();
+    plan.delete.sort();
+    plan
+}
+
+/// Copies only the files in `paths` out of `files`.
+pub fn subset(files: &FileCollection, paths: &[String]) -> FileCollection {
+    let keep = paths.iter().collect::<HashSet<&String>>();
+
+    let mut sub = FileCollection {
+        files: vec![],
+        contents: vec![],
+    };
+    for (path, contents) in files.files.iter().zip(files.contents.iter()) {
+        if keep.contains(path) {
+            sub.files.push(path.clone());
+            sub.contents.push(contents.clone());
+        }
+    }
+    sub
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collection(files: &[(&str, &str)]) -> FileCollection {
+        FileCollection {
+            files: files.iter().map(|(p, _)| p.to_string()).collect(),
+            contents: files.iter().map(|(_, c)| c.as_bytes().to_vec()).collect(),
+        }
+    }
+
+    #[test]
+    fn plan_sorts_files_into_upload_delete_and_unchanged() {
+        let remote = manifest(&collection(&[("same.txt", "a"), ("changed.txt", "b"), ("deleted.txt", "c")]));
+        let local = manifest(&collection(&[("same.txt", "a"), ("changed.txt", "b2"), ("added.txt", "d")]));
+
+        let plan = plan(&local, &remote);
+        assert_eq!(plan.upload, vec!["added.txt", "changed.txt"]);
+        assert_eq!(plan.delete, vec!["deleted.txt"]);
+        assert_eq!(plan.unchanged, 1);
+    }
+
+    #[test]
+    fn plan_against_an_empty_node_uploads_everything() {
+        let local = manifest(&collection(&[("a", "1"), ("b", "2")]));
+
+        let plan = plan(&local, &Manifest::new());
+        assert_eq!(plan.upload, vec!["a", "b"]);
+        assert!(plan.delete.is_empty());
+        assert_eq!(plan.unchanged, 0);
+    }
+
+    #[test]
+    fn identical_manifests_need_no_changes() {
+        let local = manifest(&collection(&[("a", "1"), ("b", "2")]));
+
+        let plan = plan(&local, &local.clone());
+        assert!(plan.upload.is_empty());
+        assert!(plan.delete.is_empty());
+        assert_eq!(plan.unchanged, 2);
+    }
+
+    #[test]
+    fn subset_keeps_contents_with_their_paths() {
+        let files = collection(&[("a", "1"), ("b", "2"), ("c", "3")]);
+
+        let sub = subset(&files, &["c".to_string(), "a".to_string()]);
+        assert_eq!(sub.files, vec!["a", "c"]);
+        assert_eq!(sub.contents, vec![b"1".to_vec(), b"3".to_vec()]);
+    }
+
+    #[test]
+    fn manifest_hash_ignores_order() {
+        let a = manifest(&collection(&[("a", "1"), ("b", "2")]));
+        let b = manifest(&collection(&[("b", "2"), ("a", "1")]));
+        assert_eq!(manifest_hash(&a), manifest_hash(&b));
+        assert_ne!(manifest_hash(&a), manifest_hash(&manifest(&collection(&[("a", "1")]))));
+    }
+}
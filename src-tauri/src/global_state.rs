@@ -1,64 +1,267 @@
 use tynkerbase_universal::{crypt_utils::hash_utils, netwk_utils::Node};
 use crate::consts;
 use crate::api_auth_interface::get_nodes;
+use crate::vault::{self, Credentials, SealedBox};
+use crate::settings;
+use crate::agent_interface::AgentClient;
 use serde::{Serialize, Deserialize};
-use bincode;
 use anyhow::{anyhow, Result};
 use tokio::runtime::Runtime;
 use std::{
+    collections::HashMap,
+    env,
     fs,
     path::Path
 };
 
+/// Prefix of state files written with an encrypted credential vault. Older state
+/// files are plain bincode and start with the length of the email instead.
+const STATE_MAGIC: &[u8; 4] = b"TYB2";
 
+/// Prefix of vault state files written before certificate pins were saved.
+const STATE_MAGIC_V1: &[u8; 4] = b"TYBV";
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// Profile used when neither `--profile` nor `tyb profile use` picked one. Its state
+/// lives in `global-state.bin` so that pre-profile installs keep working.
+pub const DEFAULT_PROFILE: &str = "default";
+
+
+#[derive(Debug, Clone, Default)]
 pub struct GlobalState {
+    pub profile: String,
+    /// Resolved per profile at load time, never persisted in the state file.
+    pub auth_endpoint: String,
     pub email: String,
-    pub password: String,
+    pub pass_sha256: String,
+    pub pass_sha384: String,
     pub tyb_key: String,
     pub nodes: Vec<Node>,
-    pub projects: Vec<String>
+    pub projects: Vec<String>,
+    /// Sha256 fingerprints of each node's TLS certificate, keyed by node id.
+    pub cert_pins: HashMap<String, String>,
+    /// Api key from `TYB_API_KEY`. Used instead of `tyb_key` and never saved.
+    pub api_key_override: Option<String>,
+    /// Built from the environment without a saved profile, see `from_env`. It can't be saved,
+    /// so certificates pinned while using it only last for the current run.
+    pub ephemeral: bool,
+}
+
+/// On disk representation of `GlobalState`. Secret fields live in `vault`.
+#[derive(Serialize, Deserialize)]
+struct StateFile {
+    email: String,
+    vault: SealedBox,
+    nodes: Vec<Node>,
+    projects: Vec<String>,
+    cert_pins: HashMap<String, String>,
+}
+
+/// Layout of `STATE_MAGIC_V1` state files.
+#[derive(Serialize, Deserialize)]
+struct StateFileV1 {
+    email: String,
+    vault: SealedBox,
+    nodes: Vec<Node>,
+    projects: Vec<String>,
+}
+
+/// Layout of `global-state.bin` before the credential vault was introduced.
+#[derive(Deserialize)]
+struct LegacyStateFile {
+    email: String,
+    password: String,
+    tyb_key: String,
+    nodes: Vec<Node>,
+    projects: Vec<String>,
 }
 
 impl GlobalState {
-    pub fn new(email: &str, password: &str, tyb_key: &str) -> Self {
+    pub fn new(profile: &str, auth_endpoint: &str, email: &str, password: &str, tyb_key: &str) -> Self {
         GlobalState {
+            profile: profile.to_string(),
+            auth_endpoint: auth_endpoint.to_string(),
             email: email.to_string(), 
-            password: password.to_string(),
+            pass_sha256: hash_utils::sha256(password),
+            pass_sha384: hash_utils::sha384(password),
             tyb_key: tyb_key.to_string(),
             nodes: vec![],
             projects: vec![],
+            cert_pins: HashMap::new(),
+            api_key_override: None,
+            ephemeral: false,
+        }
+    }
+
+    /// State for a run that only has an api key, like a CI job. There are no credentials to
+    /// list nodes with, so they have to be added with `resolve_nodes`.
+    pub fn from_api_key(profile: &str, auth_endpoint: &str, email: &str, api_key: &str) -> Self {
+        GlobalState {
+            profile: profile.to_string(),
+            auth_endpoint: auth_endpoint.to_string(),
+            email: email.to_string(),
+            api_key_override: Some(api_key.to_string()),
+            ephemeral: true,
+            ..Default::default()
+        }
+    }
+
+    /// Builds an ephemeral state from `TYB_API_KEY`, `TYB_EMAIL` and `TYB_NODES` when no profile
+    /// is logged in. Returns `None` if `TYB_API_KEY` isn't set.
+    pub fn from_env(profile: &str, auth_endpoint: &str) -> Result<Option<Self>> {
+        let Some(api_key) = api_key_from_env() else {
+            return Ok(None);
+        };
+        let email = env::var(consts::EMAIL_ENV).unwrap_or_default();
+        let nodes = env::var(consts::NODES_ENV)
+            .unwrap_or_default()
+            .split(',')
+            .map(|n| n.trim().to_string())
+            .filter(|n| n.len() > 0)
+            .collect::<Vec<String>>();
+        if nodes.len() == 0 {
+            return Err(anyhow!(
+                "No profile is logged in, so `{}` needs `{}` to list the nodes to use, e.g. `{}=web=https://node.example.com`",
+                consts::API_KEY_ENV, consts::NODES_ENV, consts::NODES_ENV,
+            ));
+        }
+
+        let mut state = Self::from_api_key(profile, auth_endpoint, &email, &api_key);
+        let rt = Runtime::new().unwrap();
+        rt.block_on(state.resolve_nodes(&nodes))?;
+        Ok(Some(state))
+    }
+
+    /// Adds the nodes at `addrs`, given as `addr` or `name=addr`, asking each agent for its id.
+    /// Nodes without a name are named after their id. Their certificates are pinned in memory
+    /// so the rest of the run talks to the same nodes.
+    pub async fn resolve_nodes(&mut self, addrs: &[String]) -> Result<()> {
+        for entry in addrs {
+            let (name, addr) = match entry.split_once('=') {
+                Some((name, addr)) => (Some(name.trim()), addr.trim()),
+                None => (None, entry.trim()),
+            };
+            let mut node = Node {
+                node_id: String::new(),
+                name: name.unwrap_or_default().to_string(),
+                email: self.email.clone(),
+                addr: addr.to_string(),
+            };
+
+            let client = AgentClient::new(&node, self.api_key(), None)?;
+            let id = client.get_id()
+                .await
+                .map_err(|e| anyhow!("Unable to reach node at `{}` -> {}", addr, e.report()))?;
+            node.node_id = id.trim().to_string();
+            if node.name.len() == 0 {
+                node.name = node.node_id.clone();
+            }
+            if let Some(fingerprint) = client.observed_cert() {
+                self.cert_pins.insert(node.node_id.clone(), fingerprint);
+            }
+            self.nodes.push(node);
         }
+        Ok(())
     }
 
-    pub fn path() -> String {
-        format!("{}/global-state.bin", consts::app_data())
+    pub fn path(profile: &str) -> String {
+        if profile == DEFAULT_PROFILE {
+            return format!("{}/global-state.bin", consts::app_data());
+        }
+        format!("{}/global-state.{}.bin", consts::app_data(), profile)
     }
 
-    pub fn exists() -> bool {
-        let path: String = format!("{}/global-state.bin", consts::app_data());
-        Path::new(&path).exists()
+    pub fn exists(profile: &str) -> bool {
+        Path::new(&Self::path(profile)).exists()
     }
 
-    pub fn load () -> Result<Self> {
-        let path: String = format!("{}/global-state.bin", consts::app_data());
+    /// Loads the global state, transparently migrating plaintext state files and older
+    /// layouts into the current format.
+    pub fn load (profile: &str) -> Result<Self> {
+        let path = Self::path(profile);
         let bytes = fs::read(&path)
             .map_err(|e| anyhow!("Error reading from file `{}` -> {}", &path, e))?;
-        let state: Self = bincode::deserialize(&bytes)
+        let auth_endpoint = settings::auth_endpoint(profile, None)?;
+
+        if let Some(bytes) = bytes.strip_prefix(STATE_MAGIC) {
+            let file: StateFile = bincode::deserialize(bytes)
+                .map_err(|e| anyhow!("Failed to deserialize state -> {}", e))?;
+            let creds = vault::open(&file.vault)?;
+            return Ok(GlobalState {
+                profile: profile.to_string(),
+                auth_endpoint,
+                email: file.email,
+                pass_sha256: creds.pass_sha256,
+                pass_sha384: creds.pass_sha384,
+                tyb_key: creds.tyb_key,
+                nodes: file.nodes,
+                projects: file.projects,
+                cert_pins: file.cert_pins,
+                api_key_override: api_key_from_env(),
+                ephemeral: false,
+            });
+        }
+
+        if let Some(bytes) = bytes.strip_prefix(STATE_MAGIC_V1) {
+            let file: StateFileV1 = bincode::deserialize(bytes)
+                .map_err(|e| anyhow!("Failed to deserialize state -> {}", e))?;
+            let creds = vault::open(&file.vault)?;
+            let mut state = GlobalState {
+                profile: profile.to_string(),
+                auth_endpoint,
+                email: file.email,
+                pass_sha256: creds.pass_sha256,
+                pass_sha384: creds.pass_sha384,
+                tyb_key: creds.tyb_key,
+                nodes: file.nodes,
+                projects: file.projects,
+                ..Default::default()
+            };
+            state.save()?;
+            state.api_key_override = api_key_from_env();
+            return Ok(state);
+        }
+
+        let legacy: LegacyStateFile = bincode::deserialize(&bytes)
             .map_err(|e| anyhow!("Failed to deserialize state -> {}", e))?;
+        let mut state = GlobalState::new(profile, &auth_endpoint, &legacy.email, &legacy.password, &legacy.tyb_key);
+        state.nodes = legacy.nodes;
+        state.projects = legacy.projects;
+        state.save()?;
+        state.api_key_override = api_key_from_env();
+
         Ok(state)
     }
 
     pub fn save(&self) -> Result<()> {
+        if self.ephemeral {
+            return Err(anyhow!(
+                "Nothing can be saved when using `{}` without a logged in profile, log in with `tyb login` first",
+                consts::API_KEY_ENV,
+            ));
+        }
         let app_data = consts::app_data();
         if !Path::new(&app_data).exists() {
             fs::create_dir_all(&app_data).unwrap();
         }
-        let path: String = format!("{}/global-state.bin", &app_data);
+        let path = Self::path(&self.profile);
 
-        let bytes = bincode::serialize(&self)
+        let creds = Credentials {
+            pass_sha256: self.pass_sha256.clone(),
+            pass_sha384: self.pass_sha384.clone(),
+            tyb_key: self.tyb_key.clone(),
+        };
+        let file = StateFile {
+            email: self.email.clone(),
+            vault: vault::seal(&creds)?,
+            nodes: self.nodes.clone(),
+            projects: self.projects.clone(),
+            cert_pins: self.cert_pins.clone(),
+        };
+
+        let mut bytes = STATE_MAGIC.to_vec();
+        let mut body = bincode::serialize(&file)
             .map_err(|e| anyhow!("Error serializing state -> {}", e))?;
+        bytes.append(&mut body);
         fs::write(&path, &bytes)
             .map_err(|e| anyhow!("Error saving state to file -> {}", e))?;
 
@@ -67,25 +270,179 @@ impl GlobalState {
 
     pub fn populate_nodes(&mut self) -> Result<()> {
         let rt = Runtime::new().unwrap();
-        let pass_sha256 = hash_utils::sha256(&self.password);
+        rt.block_on(self.fetch_nodes())
+    }
 
-        let f = get_nodes(&self.email, &pass_sha256);
-        let mut nodes = rt.block_on(f)?;
-        self.nodes.append(&mut nodes);
+    /// Async version of `populate_nodes` for callers that are already inside of a runtime.
+    pub async fn fetch_nodes(&mut self) -> Result<()> {
+        // Ephemeral states have no credentials to list nodes with, theirs come from `resolve_nodes`
+        if self.ephemeral {
+            return Ok(());
+        }
+        self.nodes = get_nodes(&self.auth_endpoint, &self.email, &self.pass_sha256).await?;
+        Ok(())
+    }
+
+    /// Api key sent to the nodes, `TYB_API_KEY` if it's set.
+    pub fn api_key(&self) -> &str {
+        self.api_key_override.as_deref().unwrap_or(&self.tyb_key)
+    }
+
+    /// Creates a client for `node` that only trusts the node's pinned certificate.
+    pub fn agent_client(&self, node: &Node) -> crate::error::Result<AgentClient> {
+        let pin = self.cert_pins.get(&node.node_id).map(|s| s.as_str());
+        AgentClient::new(node, self.api_key(), pin)
+    }
+
+    /// Creates a client for every node, skipping nodes a client can't be created for.
+    pub fn agent_clients(&self) -> Vec<AgentClient> {
+        self.nodes
+            .iter()
+            .filter_map(|n| self.agent_client(n).ok())
+            .collect()
+    }
+
+    /// Pins the certificates seen by `clients` for nodes that don't have a pin yet (trust on first use),
+    /// saving the state if anything changed.
+    pub fn pin_observed_certs(&mut self, clients: &[AgentClient]) -> Result<()> {
+        let mut changed = false;
+        for c in clients {
+            let node_id = &c.node().node_id;
+            if self.cert_pins.contains_key(node_id) {
+                continue;
+            }
+            if let Some(fingerprint) = c.observed_cert() {
+                self.cert_pins.insert(node_id.clone(), fingerprint);
+                changed = true;
+            }
+        }
+
+        if changed && !self.ephemeral {
+            self.save()?;
+        }
         Ok(())
     }
 }
 
+fn api_key_from_env() -> Option<String> {
+    env::var(consts::API_KEY_ENV)
+        .ok()
+        .map(|k| k.trim().to_string())
+        .filter(|k| k.len() > 0)
+}
 
+fn active_profile_path() -> String {
+    format!("{}/active-profile", consts::app_data())
+}
 
+/// Returns the profile selected with `tyb profile use`, or the default profile.
+pub fn active_profile() -> String {
+    match fs::read_to_string(active_profile_path()) {
+        Ok(p) if validate_profile_name(p.trim()).is_ok() => p.trim().to_string(),
+        _ => DEFAULT_PROFILE.to_string(),
+    }
+}
 
+pub fn set_active_profile(profile: &str) -> Result<()> {
+    validate_profile_name(profile)?;
+    let app_data = consts::app_data();
+    if !Path::new(&app_data).exists() {
+        fs::create_dir_all(&app_data).unwrap();
+    }
+    fs::write(active_profile_path(), profile)
+        .map_err(|e| anyhow!("Error saving active profile -> {}", e))
+}
 
+/// Lists every profile that has a saved state file.
+pub fn list_profiles() -> Vec<String> {
+    let mut profiles = vec![];
+    let entries = match fs::read_dir(consts::app_data()) {
+        Ok(e) => e,
+        Err(_) => return profiles,
+    };
 
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name == "global-state.bin" {
+            profiles.push(DEFAULT_PROFILE.to_string());
+        }
+        else if let Some(p) = name.strip_prefix("global-state.").and_then(|n| n.strip_suffix(".bin")) {
+            profiles.push(p.to_string());
+        }
+    }
+    profiles.sort();
+    profiles
+}
+
+/// Deletes a profile's state file. If it was the active profile, the default profile becomes active.
+pub fn remove_profile(profile: &str) -> Result<()> {
+    validate_profile_name(profile)?;
+    let path = GlobalState::path(profile);
+    if !Path::new(&path).exists() {
+        return Err(anyhow!("No profile named `{}`", profile));
+    }
+    fs::remove_file(&path)
+        .map_err(|e| anyhow!("Error removing profile `{}` -> {}", profile, e))?;
+
+    let mut s = settings::Settings::load().unwrap_or_default();
+    if s.profiles.remove(profile).is_some() {
+        s.save()?;
+    }
 
+    if active_profile() == profile {
+        set_active_profile(DEFAULT_PROFILE)?;
+    }
+    Ok(())
+}
 
+pub fn validate_profile_name(profile: &str) -> Result<()> {
+    let valid = profile.len() > 0 && profile
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
 
+    if !valid {
+        return Err(anyhow!("Profile name `{}` may only contain letters, numbers, `-` and `_`", profile));
+    }
+    Ok(())
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    /// Points the app data directory at a temporary home shared by the tests in this module.
+    fn temp_home() {
+        static HOME: std::sync::OnceLock<tempfile::TempDir> = std::sync::OnceLock::new();
+        let home = HOME.get_or_init(|| tempfile::tempdir().unwrap());
+        env::set_var("HOME", home.path());
+        fs::create_dir_all(consts::app_data()).unwrap();
+    }
 
+    #[test]
+    fn v1_state_files_are_migrated() {
+        temp_home();
+        let creds = Credentials {
+            pass_sha256: hash_utils::sha256("hunter2"),
+            pass_sha384: hash_utils::sha384("hunter2"),
+            tyb_key: "key".to_string(),
+        };
+        let v1 = StateFileV1 {
+            email: "me@example.com".to_string(),
+            vault: vault::seal(&creds).unwrap(),
+            nodes: vec![],
+            projects: vec!["web".to_string()],
+        };
+        let mut bytes = STATE_MAGIC_V1.to_vec();
+        bytes.extend(bincode::serialize(&v1).unwrap());
+        fs::write(GlobalState::path("migrate-v1"), &bytes).unwrap();
 
+        let state = GlobalState::load("migrate-v1").unwrap();
+        assert_eq!(state.email, "me@example.com");
+        assert_eq!(state.tyb_key, "key");
+        assert_eq!(state.projects, vec!["web"]);
 
+        let saved = fs::read(GlobalState::path("migrate-v1")).unwrap();
+        assert!(saved.starts_with(STATE_MAGIC));
+        assert_eq!(GlobalState::load("migrate-v1").unwrap().tyb_key, "key");
+    }
+}
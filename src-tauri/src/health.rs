@@ -0,0 +1,163 @@
+use serde::{Serialize, Deserialize};
+use reqwest::Url;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+use crate::agent_interface::AgentClient;
+use crate::error::{Result, TynkerError};
+
+/// Checked after a container is spawned, before the node counts as deployed.
+///
+/// ```json
+/// "health_check": { "type": "http", "port": 8080, "path": "/health", "timeout_secs": 60 }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthCheck {
+    #[serde(flatten)]
+    pub probe: Probe,
+    /// Host the container's port is reached on. Defaults to the host of the node's address,
+    /// which is wrong for nodes behind a tunnel like ngrok that only forwards the agent.
+    #[serde(default)]
+    pub host: Option<String>,
+    /// How long to keep polling before the node is declared unhealthy.
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Roll unhealthy nodes back to the release they were running before.
+    #[serde(default)]
+    pub rollback_on_failure: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Probe {
+    /// A plain http request to `path` on the container's port returns `expected_status`.
+    Http {
+        port: u16,
+        #[serde(default = "default_path")]
+        path: String,
+        #[serde(default = "default_expected_status")]
+        expected_status: u16,
+    },
+    /// The node accepts tcp connections on `port`.
+    Tcp {
+        port: u16,
+    },
+    /// The container is still up `secs` seconds after being spawned.
+    Running {
+        secs: u64,
+    },
+}
+
+fn default_timeout_secs() -> u64 {
+    60
+}
+
+fn default_interval_secs() -> u64 {
+    2
+}
+
+fn default_path() -> String {
+    "/".to_string()
+}
+
+fn default_expected_status() -> u16 {
+    200
+}
+
+impl HealthCheck {
+    /// Only checks that the container is still up after `secs` seconds.
+    pub fn running(secs: u64) -> Self {
+        HealthCheck {
+            probe: Probe::Running { secs },
+            host: None,
+            timeout_secs: default_timeout_secs(),
+            interval_secs: default_interval_secs(),
+            rollback_on_failure: false,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match &self.probe {
+            Probe::Http { port, path, expected_status, .. } => format!("HTTP {} on port {} returns {}", path, port, expected_status),
+            Probe::Tcp { port } => format!("port {} accepts connections", port),
+            Probe::Running { secs } => format!("container is still running after {}s", secs),
+        }
+    }
+}
+
+/// Polls the node until the health check passes or `timeout_secs` runs out.
+pub async fn check(client: &AgentClient, proj_name: &str, hc: &HealthCheck) -> Result<()> {
+    let node = client.node().name.clone();
+    let unhealthy = |reason: String| TynkerError::Unhealthy { node: node.clone(), reason };
+
+    if let Probe::Running { secs } = hc.probe {
+        // Fail as soon as the container goes down instead of waiting out the whole period
+        let start = Instant::now();
+        loop {
+            if !client.container_running(proj_name).await? {
+                return Err(unhealthy(format!("the container stopped within {}s of starting", start.elapsed().as_secs())));
+            }
+            if start.elapsed() >= Duration::from_secs(secs) {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_secs(hc.interval_secs.max(1))).await;
+        }
+    }
+
+    let host = match hc.host {
+        Some(ref h) => h.clone(),
+        None => Url::parse(client.base_url())
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.to_string()))
+            .ok_or(TynkerError::InvalidEndpoint(format!("Unable to get the host of `{}`", client.base_url())))?,
+    };
+
+    let deadline = Instant::now() + Duration::from_secs(hc.timeout_secs);
+    loop {
+        let res = match &hc.probe {
+            Probe::Http { port, path, expected_status } => probe_http(&host, *port, path, *expected_status).await,
+            Probe::Tcp { port } => probe_tcp(&host, *port).await,
+            Probe::Running { .. } => unreachable!(),
+        };
+        let err = match res {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+
+        if Instant::now() >= deadline {
+            return Err(unhealthy(format!("{} (gave up after {}s)", err, hc.timeout_secs)));
+        }
+        tokio::time::sleep(Duration::from_secs(hc.interval_secs.max(1))).await;
+    }
+}
+
+async fn probe_http(host: &str, port: u16, path: &str, expected_status: u16) -> std::result::Result<(), String> {
+    // Plain http, the container's port has no certificate that could be checked against the pin
+    let url = format!("http://{}:{}/{}", host, port, path.trim_start_matches('/'));
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .map_err(|e| format!("Error building http client -> {}", e))?;
+
+    let res = client.get(&url)
+        .send()
+        .await
+        .map_err(|e| format!("GET {} failed: {}", url, e))?;
+
+    if res.status().as_u16() != expected_status {
+        return Err(format!("GET {} returned {}, expected {}", url, res.status().as_u16(), expected_status));
+    }
+    Ok(())
+}
+
+async fn probe_tcp(host: &str, port: u16) -> std::result::Result<(), String> {
+    let connect = TcpStream::connect((host, port));
+    match tokio::time::timeout(Duration::from_secs(5), connect).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(format!("unable to connect to {}:{}: {}", host, port, e)),
+        Err(_) => Err(format!("connecting to {}:{} timed out", host, port)),
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{config, consts};
+use serde::{Serialize, Deserialize};
+use anyhow::{anyhow, Result};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+    process::Command,
+};
+
+/// A step of a deployment on a single node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Transfer,
+    Build,
+    Spawn,
+    Health,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepOutcome {
+    Succeeded,
+    Failed(String),
+    /// An earlier step failed, so this one never ran.
+    #[default]
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeOutcome {
+    pub node: String,
+    pub transfer: StepOutcome,
+    pub build: StepOutcome,
+    pub spawn: StepOutcome,
+    /// Skipped when the project has no health check.
+    #[serde(default)]
+    pub health: StepOutcome,
+    /// Release the node was rolled back to after failing its health check.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rolled_back_to: Option<String>,
+}
+
+impl NodeOutcome {
+    pub fn succeeded(&self) -> bool {
+        self.spawn == StepOutcome::Succeeded && !matches!(self.health, StepOutcome::Failed(_))
+    }
+}
+
+/// What was deployed, when, by whom and how it went on each node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeployRecord {
+    pub project: String,
+    pub version: String,
+    /// RFC 3339 timestamp of when the deployment started.
+    pub timestamp: String,
+    pub deployed_by: String,
+    pub profile: String,
+    /// Config overlay the release was deployed with, `None` for the base config.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    /// `HEAD` of the project directory, if it's a git repository.
+    pub git_commit: Option<String>,
+    pub manifest_hash: String,
+    pub nodes: Vec<NodeOutcome>,
+}
+
+impl DeployRecord {
+    pub fn new(project: &str, version: &str, deployed_by: &str, profile: &str, manifest_hash: &str, nodes: &[String]) -> Self {
+        DeployRecord {
+            project: project.to_string(),
+            version: version.to_string(),
+            timestamp: chrono::Local::now().to_rfc3339(),
+            deployed_by: deployed_by.to_string(),
+            profile: profile.to_string(),
+            environment: None,
+            git_commit: git_commit("./"),
+            manifest_hash: manifest_hash.to_string(),
+            nodes: nodes
+                .iter()
+                .map(|n| NodeOutcome {
+                    node: n.clone(),
+                    transfer: StepOutcome::Skipped,
+                    build: StepOutcome::Skipped,
+                    spawn: StepOutcome::Skipped,
+                    health: StepOutcome::Skipped,
+                    rolled_back_to: None,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn set_outcome(&mut self, node: &str, step: Step, outcome: StepOutcome) {
+        let Some(n) = self.nodes.iter_mut().find(|n| n.node == node) else {
+            return;
+        };
+        match step {
+            Step::Transfer => n.transfer = outcome,
+            Step::Build => n.build = outcome,
+            Step::Spawn => n.spawn = outcome,
+            Step::Health => n.health = outcome,
+        }
+    }
+
+    pub fn set_rolled_back(&mut self, node: &str, version: &str) {
+        if let Some(n) = self.nodes.iter_mut().find(|n| n.node == node) {
+            n.rolled_back_to = Some(version.to_string());
+        }
+    }
+
+    /// `succeeded`, `failed` or `partial` depending on how many nodes are running the release.
+    pub fn status(&self) -> &'static str {
+        let ok = self.nodes.iter().filter(|n| n.succeeded()).count();
+        if ok == self.nodes.len() {
+            "succeeded"
+        }
+        else if ok == 0 {
+            "failed"
+        }
+        else {
+            "partial"
+        }
+    }
+}
+
+pub fn dir() -> String {
+    format!("{}/history", consts::app_data())
+}
+
+/// Each project's deployments are appended to `history/<project>.jsonl` in the app data directory.
+pub fn path(project: &str) -> Result<String> {
+    config::validate_project_name(project)?;
+    Ok(format!("{}/{}.jsonl", dir(), project))
+}
+
+pub fn record(entry: &DeployRecord) -> Result<()> {
+    let path = path(&entry.project)?;
+    let dir = dir();
+    if !Path::new(&dir).exists() {
+        fs::create_dir_all(&dir)
+            .map_err(|e| anyhow!("Error creating history directory -> {}", e))?;
+    }
+    let line = serde_json::to_string(entry)
+        .map_err(|e| anyhow!("Error serializing deployment record -> {}", e))?;
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| anyhow!("Error opening history file -> {}", e))?;
+    writeln!(file, "{}", line)
+        .map_err(|e| anyhow!("Error writing to history file -> {}", e))
+}
+
+/// Deployments of `project`, newest first. Lines that can't be parsed are skipped.
+pub fn load(project: &str) -> Result<Vec<DeployRecord>> {
+    let path = path(project)?;
+    if !Path::new(&path).exists() {
+        return Ok(vec![]);
+    }
+    let text = fs::read_to_string(&path)
+        .map_err(|e| anyhow!("Error reading from file `{}` -> {}", &path, e))?;
+
+    let mut records = text
+        .lines()
+        .filter_map(|l| serde_json::from_str::<DeployRecord>(l).ok())
+        .collect::<Vec<DeployRecord>>();
+    records.reverse();
+    Ok(records)
+}
+
+/// Projects that have at least one recorded deployment.
+pub fn list_projects() -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir()) else {
+        return vec![];
+    };
+    let mut projects = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            name.strip_suffix(".jsonl").map(|n| n.to_string())
+        })
+        .collect::<Vec<String>>();
+    projects.sort();
+    projects
+}
+
+/// Commit checked out in `dir`, or `None` if it isn't a git repository (or git isn't installed).
+/// A `-dirty` suffix is added if there are uncommitted changes.
+pub fn git_commit(dir: &str) -> Option<String> {
+    let out = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(dir)
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let commit = String::from_utf8_lossy(&out.stdout).trim().to_string();
+
+    let dirty = Command::new("git")
+        .args(["status", "--porcelain"])
+        .current_dir(dir)
+        .output()
+        .map(|o| o.status.success() && !o.stdout.is_empty())
+        .unwrap_or(false);
+
+    if dirty {
+        Some(format!("{}-dirty", commit))
+    }
+    else {
+        Some(commit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn project_names_cant_leave_the_history_directory() {
+        assert!(path("web-app_2.0").is_ok());
+        for name in ["../web", "..", "a/b", "a\\b", ".hidden", ""] {
+            assert!(path(name).is_err(), "`{}` should be rejected", name);
+            assert!(load(name).is_err(), "`{}` should be rejected", name);
+        }
+    }
+}
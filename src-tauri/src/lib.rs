@@ -0,0 +1,29 @@
+//! Client library for TynkerBase. Talks to the auth server and to the agent on each node, and
+//! orchestrates deployments across them. The `tyb` CLI and the desktop GUI are front ends on
+//! top of it.
+
+pub mod agent_interface;
+pub mod api_auth_interface;
+pub mod config;
+pub mod deploy;
+pub mod error;
+pub mod exec;
+pub mod file_sync;
+pub mod global_state;
+pub mod health;
+pub mod history;
+pub mod secrets;
+pub mod settings;
+
+pub(crate) mod cert_pinning;
+pub(crate) mod consts;
+pub(crate) mod upload;
+pub(crate) mod vault;
+
+pub use agent_interface::AgentClient;
+pub use cert_pinning::display_fingerprint;
+pub use consts::{API_KEY_ENV, BUILD_STATUS_PREFIX, EMAIL_ENV, NODES_ENV, PROJ_JSON_CONFIG};
+pub use deploy::{DeployReport, Deployment, Reporter};
+pub use error::{Result, TynkerError};
+pub use global_state::GlobalState;
+pub use upload::Compression;
@@ -4,12 +4,11 @@
 
 #![allow(unused)] // TEMPORARY, REMOVE BEFORE PROD  
 
-mod agent_interface;
-mod api_auth_interface;
-mod consts;
-mod global_state;
+#[cfg(feature = "gui")]
 mod tauri_cmds;
+mod output;
 
+#[cfg(feature = "gui")]
 use tauri;
 use reqwest::header::ACCEPT;
 use tynkerbase_universal::{
@@ -23,7 +22,25 @@ use tynkerbase_universal::{
     file_utils::{self, FileCollection},
     netwk_utils::{Node, ProjConfig},
 };
-use consts::PROJ_JSON_CONFIG;
+use tynkerbase_client::{
+    agent_interface,
+    api_auth_interface,
+    config,
+    deploy::{self, Deployment, Reporter},
+    error,
+    exec,
+    file_sync,
+    global_state,
+    health,
+    history,
+    secrets,
+    settings,
+    display_fingerprint,
+    Compression,
+    API_KEY_ENV,
+    NODES_ENV,
+    PROJ_JSON_CONFIG,
+};
 use std::{
     fs::{self, remove_file},
     path::Path, 
@@ -31,23 +48,28 @@ use std::{
 };
 use std::env;
 use std::sync::{Mutex, Arc};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use std::collections::HashMap;
 use std::io::{self, Write};
 use rpassword::read_password;
 use bincode;
 use tokio::{self, runtime::Runtime, sync::Mutex as TkMutex};
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use prettytable::{Table, Row, Cell, row};
 use ansi_term::Colour::{Red, Blue};
 use ansi_term::Style;
 
 use global_state::GlobalState;
+use agent_interface::{AgentClient, ContainerAction, LogOptions, SyncSummary};
+use error::TynkerError;
 
+#[cfg(feature = "gui")]
 fn launch_gui(state: GlobalState) {
     let state = Arc::new(TkMutex::new(state));
 
     tauri::Builder::default()
         .manage(state)
+        .manage(tauri_cmds::LogStreams::default())
         .invoke_handler(tauri::generate_handler![
             tauri_cmds::ping,
             tauri_cmds::list_nodes,
@@ -56,12 +78,33 @@ fn launch_gui(state: GlobalState) {
             tauri_cmds::get_container_stats,
             tauri_cmds::create_account,
             tauri_cmds::delete_account,
+            tauri_cmds::list_profiles,
+            tauri_cmds::switch_profile,
+            tauri_cmds::build_image,
+            tauri_cmds::stop_container,
+            tauri_cmds::start_container,
+            tauri_cmds::restart_container,
+            tauri_cmds::remove_container,
+            tauri_cmds::start_log_stream,
+            tauri_cmds::stop_log_stream,
+            tauri_cmds::list_history_projects,
+            tauri_cmds::get_history,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
 
+/// Exits with `hint` instead of prompting when `--non-interactive` is set.
+fn require_interactive(non_interactive: bool, hint: &str) {
+    if non_interactive {
+        output::fail(format!("Input required, but `--non-interactive` is set. {}", hint));
+    }
+}
+
 fn prompt_node<'a>(gstate: &'a GlobalState) -> &'a Node {
+    if gstate.nodes.len() == 0 {
+        output::fail(TynkerError::NoNodes.report());
+    }
     for (i, n) in gstate.nodes.iter().enumerate() {
         println!("{})\t{}", i, &n.name);
     }
@@ -69,18 +112,170 @@ fn prompt_node<'a>(gstate: &'a GlobalState) -> &'a Node {
     let idx = crypt_utils::prompt("Choose a node number: ");
     let idx: usize = match idx.parse() {
         Ok(r) => r,
-        _ => {
-            println!("`{}` is not a valid number", idx);
-            process::exit(0);
-        }
+        _ => output::fail(format!("`{}` is not a valid number", idx)),
     };
     if idx >= gstate.nodes.len() {
-        println!("`{}` is out of range.", idx);
-        process::exit(0);
+        output::fail(format!("`{}` is out of range.", idx));
     }
     &gstate.nodes[idx]
 }
 
+fn find_node<'a>(gstate: &'a GlobalState, name: &str) -> &'a Node {
+    match gstate.nodes.iter().find(|n| n.name == name) {
+        Some(n) => n,
+        None => output::fail(format!("No node named `{}`", name)),
+    }
+}
+
+/// Clients for the named nodes, or for every node if `names` is empty.
+fn clients_for(gstate: &GlobalState, names: &[String], format: output::Format) -> Vec<AgentClient> {
+    let nodes = if names.len() == 0 {
+        gstate.nodes.clone()
+    }
+    else {
+        names.iter().map(|n| find_node(gstate, n).clone()).collect()
+    };
+
+    let mut clients = vec![];
+    for node in nodes.iter() {
+        match gstate.agent_client(node) {
+            Ok(c) => clients.push(c),
+            Err(e) => output::note(format, format!("WARNING: unable to create a client for node `{}` -> {}", node.name, e.report())),
+        }
+    }
+    clients
+}
+
+/// Prints a deployment's progress, with an upload bar per node.
+struct CliReporter {
+    bars: MultiProgress,
+    node_bars: Mutex<HashMap<String, ProgressBar>>,
+    /// Prefix build logs with the node's name when building on more than one node.
+    prefix_logs: Mutex<bool>,
+    format: output::Format,
+}
+
+impl CliReporter {
+    fn new(format: output::Format) -> Self {
+        CliReporter {
+            bars: MultiProgress::new(),
+            node_bars: Mutex::new(HashMap::new()),
+            prefix_logs: Mutex::new(false),
+            format,
+        }
+    }
+
+    fn say(&self, msg: impl std::fmt::Display) {
+        output::note(self.format, msg);
+    }
+
+    fn finish_bar(&self, node: &str) {
+        if let Some(bar) = self.node_bars.lock().unwrap().remove(node) {
+            bar.finish();
+        }
+    }
+}
+
+impl Reporter for CliReporter {
+    fn batch_started(&self, index: usize, total: usize, nodes: &[String]) {
+        *self.prefix_logs.lock().unwrap() = nodes.len() > 1;
+        if total > 1 {
+            self.say(format!("\nBatch {}/{}: {}", index + 1, total, nodes.join(", ")));
+        }
+    }
+
+    fn step_started(&self, step: history::Step) {
+        match step {
+            history::Step::Transfer => self.say("Syncing files..."),
+            history::Step::Build => self.say("Building Images (this may take a while) ..."),
+            history::Step::Spawn => self.say("Starting up containers..."),
+            history::Step::Health => {},
+        }
+    }
+
+    fn upload_progress(&self, node: &str, sent: u64, total: u64) {
+        let mut node_bars = self.node_bars.lock().unwrap();
+        let bar = node_bars.entry(node.to_string()).or_insert_with(|| {
+            let style = ProgressStyle::with_template("{prefix:>15} [{bar:30.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec})")
+                .unwrap()
+                .progress_chars("=> ");
+            let bar = self.bars.add(ProgressBar::new(0));
+            bar.set_style(style);
+            bar.set_prefix(node.to_string());
+            bar
+        });
+        bar.set_length(total);
+        bar.set_position(sent);
+    }
+
+    fn synced(&self, node: &str, s: &SyncSummary) {
+        self.finish_bar(node);
+        if s.full_upload {
+            self.say(format!("Node `{}`: uploaded all {} files", node, s.uploaded));
+        }
+        else {
+            self.say(format!("Node `{}`: {} uploaded, {} deleted, {} unchanged", node, s.uploaded, s.deleted, s.unchanged));
+        }
+    }
+
+    fn build_log(&self, node: &str, line: &str) {
+        if *self.prefix_logs.lock().unwrap() {
+            self.say(format!("{} {}", Blue.bold().paint(format!("[{}]", node)), line));
+        }
+        else {
+            self.say(line);
+        }
+    }
+
+    fn waiting(&self, secs: u64) {
+        self.say(format!("Waiting {}s before checking health...", secs));
+    }
+
+    fn checking_health(&self, check: &health::HealthCheck) {
+        self.say(format!("Checking health ({}) ...", check.describe()));
+    }
+
+    fn healthy(&self, node: &str) {
+        self.say(format!("Node `{}`: healthy", node));
+    }
+
+    // Failures are listed in the error summary once the deployment is over
+    fn failed(&self, node: &str, step: history::Step, _error: &TynkerError) {
+        if step == history::Step::Transfer {
+            self.finish_bar(node);
+        }
+    }
+
+    fn rolled_back(&self, node: &str, version: &str) {
+        self.say(format!("Node `{}`: unhealthy, rolled back to release `{}`", node, version));
+    }
+
+    fn halted(&self, index: usize, remaining: usize) {
+        self.say(format!("Batch {} failed, halting the deployment. {} node(s) were left on their previous release.", index + 1, remaining));
+    }
+
+    fn promote(&self, canary: &str, version: &str, remaining: usize) -> bool {
+        let question = format!(
+            "Canary `{}` is healthy. Promote release `{}` to the remaining {} node(s)? [y/N]: ", 
+            canary, version, remaining
+        );
+        let answer = if self.format.is_machine() {
+            // Keep the question out of the json/yaml on stdout
+            eprint!("{}", question);
+            let mut answer = String::new();
+            io::stdin().read_line(&mut answer).ok();
+            answer
+        }
+        else {
+            crypt_utils::prompt(&question)
+        };
+        if answer.trim().to_lowercase() != "y" {
+            self.say("Release not promoted, the remaining nodes keep their previous release.");
+            return false;
+        }
+        true
+    }
+}
 
 #[derive(Parser)]
 #[command(version = "0.0.1")]
@@ -88,12 +283,35 @@ fn prompt_node<'a>(gstate: &'a GlobalState) -> &'a Node {
 struct Cli {
     #[command(subcommand)]
     command: Option<TopLevelCmds>,
+
+    /// Account profile to use instead of the active one.
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// Auth server to use for this invocation. Overrides `TYB_AUTH_ENDPOINT` and `settings.json`.
+    #[arg(long, global = true)]
+    auth_endpoint: Option<String>,
+
+    /// How to print results. `json` and `yaml` are meant for scripts, progress and warnings go to stderr.
+    #[arg(long, short, global = true, value_enum, default_value_t = output::Format::Table)]
+    output: output::Format,
+
+    /// Fail instead of prompting for input, for CI and scripts.
+    #[arg(long, global = true)]
+    non_interactive: bool,
 }
 
 #[derive(Subcommand, PartialEq, Eq)]
 enum TopLevelCmds {
     Gui,
-    Login,
+    /// Log in, prompting for anything that isn't given as a flag
+    Login {
+        #[arg(long, short)]
+        email: Option<String>,
+        /// Read the password from stdin, e.g. `echo "$PASS" | tyb login -e me@x.com --password-stdin`
+        #[arg(long, requires = "email")]
+        password_stdin: bool,
+    },
     Logout,
     Help,
     CreateAccount {
@@ -102,7 +320,14 @@ enum TopLevelCmds {
         #[arg(long, short)]
         password: String,
     },
-    Deploy,
+    Deploy {
+        /// Compression used when uploading project files
+        #[arg(long, value_enum, default_value_t = Compression::default())]
+        compression: Compression,
+        /// Environment to deploy to, merges `tynkerbase-config.<env>.json` over the base config
+        #[arg(long = "env")]
+        environment: Option<String>,
+    },
     Init {
         #[arg(long, default_value_t = String::new())]
         name: String
@@ -112,223 +337,547 @@ enum TopLevelCmds {
         #[arg(long, default_value_t = String::new())]
         name: String,
     },
+    /// List the containers running on each node
+    Containers {
+        /// Nodes to list, defaults to every node
+        #[arg(long = "node")]
+        nodes: Vec<String>,
+    },
+    /// Show the hardware diagnostics of each node
+    Diags {
+        /// Nodes to show, defaults to every node
+        #[arg(long = "node")]
+        nodes: Vec<String>,
+    },
     AddUpstream {
         #[arg(long, default_value_t = String::new())]
         name: String
-    }
+    },
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCmds,
+    },
+    Node {
+        #[command(subcommand)]
+        command: NodeCmds,
+    },
+    Rollback {
+        /// Release to restore, defaults to the release before the current one
+        #[arg(long)]
+        to: Option<String>,
+        /// Nodes to roll back, defaults to all of the project's upstream nodes
+        #[arg(long = "node")]
+        nodes: Vec<String>,
+        /// Environment whose upstream nodes are rolled back
+        #[arg(long = "env")]
+        environment: Option<String>,
+    },
+    /// Stop, start, restart or remove a project's container
+    Container {
+        #[command(subcommand)]
+        command: ContainerCmds,
+    },
+    /// Show the output of a project's container
+    Logs {
+        project: String,
+        /// Nodes to show logs from, defaults to every node the project is deployed on
+        #[arg(long = "node")]
+        nodes: Vec<String>,
+        /// Keep printing new lines as they're written
+        #[arg(long, short)]
+        follow: bool,
+        /// Only show lines newer than a duration (e.g. `10m`, `1h30m`) or a timestamp
+        #[arg(long)]
+        since: Option<String>,
+        /// Only show the last N lines from each node
+        #[arg(long)]
+        tail: Option<u32>,
+    },
+    /// Run a command in a project's container, e.g. `tyb exec my-proj -t -- sh`
+    Exec {
+        project: String,
+        /// Node to run the command on, prompts if the account has more than one node
+        #[arg(long)]
+        node: Option<String>,
+        /// Allocate a terminal for interactive programs such as shells
+        #[arg(long, short)]
+        tty: bool,
+        #[arg(last = true, required = true)]
+        cmd: Vec<String>,
+    },
+    /// Manage the environment variables passed to the project's container
+    Env {
+        #[command(subcommand)]
+        command: EnvCmds,
+    },
+    /// Manage secret environment variables, stored encrypted outside of the project
+    Secret {
+        #[command(subcommand)]
+        command: SecretCmds,
+    },
+    /// Check the project config
+    Config {
+        #[command(subcommand)]
+        command: ConfigCmds,
+    },
+    /// Show past deployments of the project
+    History {
+        /// Project to show, defaults to the project in the current directory
+        #[arg(long)]
+        project: Option<String>,
+        /// Maximum number of deployments to show
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand, PartialEq, Eq)]
+enum ContainerCmds {
+    Stop(ContainerTarget),
+    Start(ContainerTarget),
+    Restart(ContainerTarget),
+    Rm(ContainerTarget),
+}
+
+#[derive(Args, PartialEq, Eq)]
+struct ContainerTarget {
+    project: String,
+    /// Nodes to act on, defaults to every node the project is deployed on
+    #[arg(long = "node")]
+    nodes: Vec<String>,
+}
+
+#[derive(Subcommand, PartialEq, Eq)]
+enum ConfigCmds {
+    /// Check `tynkerbase-config.json` and its overlays, exits with 1 if there are errors
+    Validate {
+        /// Only check this environment's overlay, defaults to every overlay found
+        #[arg(long = "env")]
+        environment: Option<String>,
+        /// Don't check that the upstream nodes exist, for use without logging in
+        #[arg(long)]
+        skip_nodes: bool,
+    },
+}
+
+#[derive(Subcommand, PartialEq, Eq)]
+enum EnvCmds {
+    /// Set variables, e.g. `tyb env set PORT=8080 LOG_LEVEL=info`
+    Set {
+        #[arg(required = true)]
+        vars: Vec<String>,
+    },
+    Unset {
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+    /// List variables, secrets are masked unless `--show-secrets` is given
+    List {
+        #[arg(long)]
+        show_secrets: bool,
+    },
+}
+
+#[derive(Subcommand, PartialEq, Eq)]
+enum SecretCmds {
+    /// Set a secret, its value is read from a prompt so it doesn't end up in shell history
+    Set {
+        key: String,
+    },
+    Unset {
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, PartialEq, Eq)]
+enum NodeCmds {
+    /// Pin the certificate the node currently presents
+    Trust {
+        name: String,
+    },
+    /// Forget the pinned certificate, the next connection will pin a new one
+    ForgetCert {
+        name: String,
+    },
+}
+
+#[derive(Subcommand, PartialEq, Eq)]
+enum ProfileCmds {
+    List,
+    Use {
+        name: String,
+    },
+    Remove {
+        name: String,
+    },
 }
 
 
-fn login() -> GlobalState {
+fn login(profile: &str, auth_endpoint: &str, email: Option<String>, password_stdin: bool, non_interactive: bool) -> GlobalState {
     let rt = tokio::runtime::Runtime::new().unwrap();
 
-    let email = crypt_utils::prompt("Enter your email: ");
-    let pass = crypt_utils::prompt_secret("Enter your password: ");
+    let email = match email {
+        Some(e) => e,
+        None => {
+            require_interactive(non_interactive, "Use `--email` and `--password-stdin` to log in.");
+            crypt_utils::prompt("Enter your email: ")
+        }
+    };
+    let pass = if password_stdin {
+        let mut pass = String::new();
+        if let Err(e) = io::stdin().read_line(&mut pass) {
+            output::fail(format!("Error reading password from stdin -> {}", e));
+        }
+        let pass = pass.trim_end_matches(&['\r', '\n']).to_string();
+        if pass.len() == 0 {
+            output::fail("No password was given on stdin.");
+        }
+        pass
+    }
+    else {
+        require_interactive(non_interactive, "Use `--password-stdin` to pass the password.");
+        crypt_utils::prompt_secret("Enter your password: ")
+    };
 
-    let key = api_auth_interface::login(&email, &pass);
+    let key = api_auth_interface::login(auth_endpoint, &email, &pass);
     let key = match rt.block_on(key) {
             Ok(r) => r,
             Err(e) => {
-                println!("Error logging in: {e}");
-                let path = GlobalState::path();
+                let path = GlobalState::path(profile);
                 if Path::new(&path).exists() {
                     fs::remove_file(&path).unwrap();
                 }
-                std::process::exit(1);
+                output::fail(format!("Error logging in: {}", e.report()));
             }
     };
-    let mut gstate = GlobalState::default();
-    gstate.email = email;
-    gstate.password = pass;
-    gstate.tyb_key = key;
-    gstate.save().unwrap();
+    let gstate = GlobalState::new(profile, auth_endpoint, &email, &pass, &key);
+    if let Err(e) = gstate.save() {
+        output::fail(format!("Error saving credentials -> {}", e));
+    }
     gstate
 }
 
-fn handle_gstate(gstate: &Option<GlobalState>) -> GlobalState{
-    match gstate {
-        Some(ref gs) => gs.clone(),
-        None => {
-            println!("Login with `tyb login` first.");
-            process::exit(1);
-        }
+/// Returns the logged in state, or one built from `TYB_API_KEY` if no profile is logged in.
+fn handle_gstate(gstate: &Option<GlobalState>, profile: &str, auth_endpoint: &str) -> GlobalState {
+    if let Some(ref gs) = gstate {
+        return gs.clone();
+    }
+    match GlobalState::from_env(profile, auth_endpoint) {
+        Ok(Some(gs)) => gs,
+        Ok(None) => output::fail(format!(
+            "Login with `tyb login` first (or select another profile with `tyb profile use`).\n\
+            In CI, set `{}` and `{}` instead.", API_KEY_ENV, NODES_ENV,
+        )),
+        Err(e) => output::fail(e),
+    }
+}
+
+/// Reads the project config, merged with `environment`'s overlay if given. Problems with it are
+/// printed, exiting if any of them are errors.
+fn read_proj_config(environment: Option<&str>, format: output::Format) -> (ProjConfig, config::DeployConfig) {
+    if !Path::new(PROJ_JSON_CONFIG).exists() {
+        output::fail("Error, not a valid tynkerbase project");
+    }
+
+    let issues = config::validate(PROJ_JSON_CONFIG, environment, None);
+    for issue in issues.iter() {
+        output::note(format, issue);
+    }
+    if issues.iter().any(|i| i.severity == config::Severity::Error) {
+        process::exit(1);
+    }
+
+    match config::load_env(PROJ_JSON_CONFIG, environment) {
+        Ok(c) => c,
+        Err(e) => output::fail(e),
     }
 }
 
 fn main() {
 
     // Parse CLI commands
-    let mut command = Cli::parse();
-    let command = command.command.unwrap_or(TopLevelCmds::Gui);
+    let mut cli = Cli::parse();
+    let format = cli.output;
+    let non_interactive = cli.non_interactive;
+    // Headless builds have no GUI to fall back to
+    let default_cmd = if cfg!(feature = "gui") { TopLevelCmds::Gui } else { TopLevelCmds::Help };
+    let command = cli.command.unwrap_or(default_cmd);
+
+    let profile = cli.profile.unwrap_or_else(global_state::active_profile);
+    if let Err(e) = global_state::validate_profile_name(&profile) {
+        output::fail(e);
+    }
+
+    let auth_endpoint = match settings::auth_endpoint(&profile, cli.auth_endpoint.as_deref()) {
+        Ok(e) => e,
+        Err(e) => output::fail(format!("Invalid auth endpoint: {}", e)),
+    };
 
     // Create tokio runtime
     let rt = tokio::runtime::Runtime::new().unwrap();
 
-    // Load Global State
-    let mut gstate = if GlobalState::exists() {
-        Some(GlobalState::load().unwrap())
+    // Load Global State. A state that can't be opened (e.g. the vault key changed) can only
+    // be replaced, so logging in and out still work without it.
+    let mut gstate = None;
+    if GlobalState::exists(&profile) {
+        match GlobalState::load(&profile) {
+            Ok(gs) => gstate = Some(gs),
+            Err(e) => {
+                let msg = format!(
+                    "Unable to load profile `{}` -> {}\nUse `tyb logout` and `tyb login` to replace the saved credentials.",
+                    &profile, e,
+                );
+                match command {
+                    TopLevelCmds::Login { .. } | TopLevelCmds::Logout => output::note(format, msg),
+                    _ => output::fail(msg),
+                }
+            }
+        }
     }
-    else {
-        None
-    };
 
     if let Some(ref mut gs) = gstate {
-        gs.populate_nodes().unwrap();
+        gs.auth_endpoint = auth_endpoint.clone();
+        if let Err(e) = gs.populate_nodes() {
+            output::note(format, format!("Warning, unable to refresh the node list, using the saved one -> {}", e));
+        }
     }
 
 
     match command {
+        #[cfg(feature = "gui")]
         TopLevelCmds::Gui => {
-            let gstate = handle_gstate(&gstate);
+            let gstate = handle_gstate(&gstate, &profile, &auth_endpoint);
             launch_gui(gstate);
             process::exit(0);
         },
-        TopLevelCmds::Login => {
+        #[cfg(not(feature = "gui"))]
+        TopLevelCmds::Gui => {
+            output::fail("This build of tyb was compiled without the GUI. Rebuild with `--features gui` to use `tyb gui`.");
+        },
+        TopLevelCmds::Login { email, password_stdin } => {
             match gstate.as_ref() {
                 Some(gstate) => {
-                    println!("You're already logged in as {}", gstate.email);
+                    output::note(format, format!("You're already logged in as {} on profile `{}`", gstate.email, &profile));
                 },
                 None => {
-                    login();
-                    println!("Logged in successfully!");
+                    login(&profile, &auth_endpoint, email, password_stdin, non_interactive);
+                    if cli.auth_endpoint.is_some() {
+                        // Remember the self hosted auth server for this profile
+                        let mut s = settings::Settings::load().unwrap_or_default();
+                        s.profiles.entry(profile.clone()).or_default().auth_endpoint = Some(auth_endpoint.clone());
+                        s.save().unwrap();
+                    }
+                    output::note(format, "Logged in successfully!");
                 }
             }
             process::exit(0);
         },
         TopLevelCmds::Logout => {
-            match gstate.as_ref() {
-                Some(_) => {
-                    let path = GlobalState::path();
-                    if Path::new(&path).exists() {
-                        fs::remove_file(path).unwrap();
-                    }
-                    println!("Logged out.");
-                },
-                None => {
-                    println!("You're already logged out!");
-                    process::exit(0);
-                }
+            // Checks the file rather than `gstate` so states that fail to load can be removed
+            if !GlobalState::exists(&profile) {
+                output::note(format, "You're already logged out!");
+                process::exit(0);
+            }
+            if let Err(e) = fs::remove_file(GlobalState::path(&profile)) {
+                output::fail(format!("Error removing the saved credentials -> {}", e));
             }
+            output::note(format, "Logged out.");
             process::exit(0);
         },
         TopLevelCmds::CreateAccount { email, password } => {
-            let f = api_auth_interface::create_account(&email, &password);
-            let res = rt.block_on(f);
-            res.unwrap();
+            let f = api_auth_interface::create_account(&auth_endpoint, &email, &password);
+            if let Err(e) = rt.block_on(f) {
+                output::fail(format!("Error creating account: {}", e.report()));
+            }
             process::exit(0);
         }
-        TopLevelCmds::Deploy => {
-            let gstate = handle_gstate(&gstate);
+        TopLevelCmds::Deploy { compression, environment } => {
+            let mut gstate = handle_gstate(&gstate, &profile, &auth_endpoint);
 
-            let conf = fs::read_to_string(PROJ_JSON_CONFIG)
-                .expect("Error, not a valid tynkerbase project");
-            let mut conf: ProjConfig = serde_json::from_str(&conf).unwrap();
+            let (mut conf, deploy_conf) = read_proj_config(environment.as_deref(), format);
             if !conf.parse_name() {
-                println!("Warning, project name must adhere to docker's naming conventions: \
-                Changing the name to `{}`", &conf.proj_name);
-                let conf_str = serde_json::to_string_pretty(&conf).unwrap();
-                fs::write(PROJ_JSON_CONFIG, conf_str)
+                output::note(format, format!("Warning, project name must adhere to docker's naming conventions: \
+                Changing the name to `{}`", &conf.proj_name));
+                config::set_proj_name(PROJ_JSON_CONFIG, &conf.proj_name)
                     .expect("Unable to write to config file.");
             }
 
             if conf.node_names.len() == 0 {
-                println!("No upstream nodes set. Use `tyb add-upstream` configure an upstream node");
-                process::exit(0);
+                output::fail("No upstream nodes set. Use `tyb add-upstream` configure an upstream node");
             }
 
             if !Path::new("Dockerfile").exists() {
-                println!("Please create docker file before deploying");
-                process::exit(0);
+                output::fail("Please create docker file before deploying");
             }
 
-            let mut endpoints = vec![];
-
-            'loop1: for n in conf.node_names.iter() {
-                for nl in gstate.nodes.iter() {
-                    if n == &nl.name {
-                        endpoints.push(nl);
-                        continue 'loop1;
-                    }
-                }
-                println!("WARNING: no upstream node found for node name `{}`", &n);
-                #[cfg(debug_assertions)] println!("\n\nNodes: \n{:#?}\n\n", gstate.nodes);
+            let (endpoints, warnings) = deploy::upstream_clients(&gstate, &conf);
+            for w in warnings.iter() {
+                output::note(format, format!("WARNING: {}", w));
             }
-
             if endpoints.len() == 0 {
-                println!("No valid upstream nodes found.");
-                process::exit(0);
+                output::fail("No valid upstream nodes found.");
             }
+            let endpoints = endpoints
+                .into_iter()
+                .map(|c| c.with_compression(compression))
+                .collect::<Vec<AgentClient>>();
+            let clients = endpoints.clone();
 
-            let mut failed_deployments = vec![];
+            let spawn_opts = match deploy::spawn_options(&conf, &deploy_conf) {
+                Ok(o) => o,
+                Err(e) => output::fail(e),
+            };
 
             let files = file_utils::FileCollection::load("./", &conf.ignore)
                 .unwrap();
 
-            println!("Transferring files...\nPayload Size: {} MB", files.sizeof() as f64 / 1_000_000.);
-            let mut handles = vec![];
-            for &e in endpoints.iter() {
-                let f = agent_interface::deploy_proj(&e.addr, &conf.proj_name, &gstate.tyb_key, &files);
-                handles.push((f, e));
-            }
-
-            for (handle, node) in handles {
-                let res = rt.block_on(handle);
-                if let Err(e) = res {
-                    failed_deployments.push(format!("Failed to transfer files to node `{}` -> {}", &node.name, e));
-                    endpoints.retain(|&e| e.name != node.name);
+            let strategy = deploy_conf.strategy.clone();
+            let batches = strategy.batches(endpoints.len()).len();
+            if let config::Strategy::Canary { auto_promote: false, .. } = strategy {
+                if batches > 1 && non_interactive {
+                    output::fail("Input required, but `--non-interactive` is set. \
+                    Set `auto_promote` in the canary strategy to promote the release without asking.");
                 }
             }
+            let deployment = Deployment::new(conf, files, endpoints)
+                .with_settings(deploy_conf)
+                .with_spawn_options(spawn_opts)
+                .with_author(&gstate.email, &gstate.profile)
+                .with_environment(environment.clone());
 
-            if endpoints.len() > 0 {
-                println!("Building Images (this may take a while) ...");
+            match environment {
+                Some(ref env) => output::note(format, format!("Deploying release `{}` to `{}`", deployment.version(), env)),
+                None => output::note(format, format!("Deploying release `{}`", deployment.version())),
             }
-            let mut handles = vec![];
-            for &e in endpoints.iter() {
-                let f = agent_interface::build_img(&e.addr, &conf.proj_name, &gstate.tyb_key);
-                handles.push((f, e));
+            output::note(format, format!("Project Size: {} MB", deployment.size() as f64 / 1_000_000.));
+            if batches > 1 {
+                output::note(format, format!("Rolling out with the `{}` strategy in {} batches", strategy.name(), batches));
             }
 
-            for (handle, node) in handles {
-                let res = rt.block_on(handle);
-                if let Err(e) = res {
-                    failed_deployments.push(format!("Failed to build image on node `{}` -> {}", node.name, e));
-                    endpoints.retain(|&e| e.name != node.name);
+            let report = rt.block_on(deployment.run(Arc::new(CliReporter::new(format))));
+
+            if let Err(e) = history::record(&report.record) {
+                output::note(format, format!("WARNING: unable to record deployment in history -> {}", e));
+            }
+
+            if let Err(e) = gstate.pin_observed_certs(&clients) {
+                output::note(format, format!("WARNING: unable to save node certificates -> {}", e));
+            }
 
+            let summary = output::DeploySummary {
+                record: &report.record,
+                status: report.record.status(),
+                failures: &report.failures,
+            };
+            output::print(format, &summary, || {
+                if report.failures.len() != 0 {
+                    println!("\n\n\nERROR SUMMARY:\n");
+                    for (i, msg) in report.failures.iter().enumerate() {
+                        println!("{:2<})    {}\n\n", i, msg);
+                    }
+                    println!("Nodes that failed keep running their previous release. Use `tyb rollback` to restore an older one.");
                 }
+            });
+            process::exit(if report.failures.len() == 0 { 0 } else { 1 });
+        }
+        TopLevelCmds::Rollback { to, nodes, environment } => {
+            let mut gstate = handle_gstate(&gstate, &profile, &auth_endpoint);
+
+            let (mut conf, _) = read_proj_config(environment.as_deref(), format);
+            conf.parse_name();
+
+            let node_names = if nodes.len() > 0 { nodes } else { conf.node_names.clone() };
+            if node_names.len() == 0 {
+                output::fail("No upstream nodes set. Use `--node` to choose which nodes to roll back.");
             }
 
-            if endpoints.len() > 0 {
-                println!("Starting up containers...");
+            let clients = clients_for(&gstate, &node_names, format);
+            let mut results = vec![];
+            for client in clients.iter() {
+                let res = match to {
+                    Some(ref v) => rt.block_on(client.rollback(&conf.proj_name, v)).map(|_| v.clone()),
+                    None => rt.block_on(deploy::rollback_to_previous(client, &conf.proj_name)),
+                };
+                results.push(output::RollbackResult::new(&client.node().name, res.map_err(|e| e.report())));
             }
-            let mut handles = vec![];
-            for &e in endpoints.iter() {
-                let f = agent_interface::spawn_container(&e.addr, &conf, &gstate.tyb_key);
-                handles.push((f, e));
+
+            if let Err(e) = gstate.pin_observed_certs(&clients) {
+                output::note(format, format!("WARNING: unable to save node certificates -> {}", e));
             }
 
-            for (handle, node) in handles {
-                let res = rt.block_on(handle);
-                if let Err(e) = res {
-                    failed_deployments.push(format!("Failed to spawn container on node `{}` -> {}", node.name, e));
+            output::print(format, &results, || {
+                for r in results.iter() {
+                    match (&r.version, &r.error) {
+                        (Some(v), _) => println!("Node `{}`: rolled back to release `{}`", r.node, v),
+                        (_, e) => println!("Node `{}`: failed to roll back -> {}", r.node, e.as_deref().unwrap_or_default()),
+                    }
                 }
+            });
+            let failed = results.len() < node_names.len() || results.iter().any(|r| r.error.is_some());
+            process::exit(if failed { 1 } else { 0 });
+        }
+        TopLevelCmds::History { project, limit } => {
+            let project = match project {
+                Some(p) => p,
+                None => {
+                    if !Path::new(PROJ_JSON_CONFIG).exists() {
+                        output::fail("Error, not a valid tynkerbase project. Use `--project` to choose a project");
+                    }
+                    let (mut conf, _) = read_proj_config(None, format);
+                    conf.parse_name();
+                    conf.proj_name
+                }
+            };
+
+            let records = match history::load(&project) {
+                Ok(r) => r,
+                Err(e) => output::fail(format!("Error reading deployment history -> {}", e)),
+            };
+            if records.len() == 0 && !format.is_machine() {
+                output::note(format, format!("No deployments recorded for project `{}`.", &project));
+                process::exit(0);
+            }
+            if format.is_machine() {
+                let records = records.iter().take(limit).collect::<Vec<&history::DeployRecord>>();
+                output::print(format, &records, || {});
+                process::exit(0);
             }
 
-            if failed_deployments.len() != 0 {
-                println!("\n\n\nERROR SUMMARY:\n");
-                for (i, msg) in failed_deployments.iter().enumerate() {
-                    println!("{:2<})    {}\n\n", i, msg);
-                }
+            let mut table = Table::new();
+            table.set_titles(row!["Release", "Deployed At", "By", "Commit", "Manifest", "Nodes", "Status"]);
+            for r in records.iter().take(limit) {
+                let commit = r.git_commit
+                    .as_ref()
+                    .map(|c| c.chars().take(10).collect::<String>())
+                    .unwrap_or("-".to_string());
+                let nodes = r.nodes
+                    .iter()
+                    .map(|n| match (&n.rolled_back_to, n.succeeded()) {
+                        (Some(v), _) => format!("{} (rolled back to {})", n.node, v),
+                        (None, true) => n.node.clone(),
+                        (None, false) => format!("{} (failed)", n.node),
+                    })
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                let release = match r.environment {
+                    Some(ref env) => format!("{} ({})", r.version, env),
+                    None => r.version.clone(),
+                };
+                let hash = r.manifest_hash.chars().take(12).collect::<String>();
+                table.add_row(row![release, &r.timestamp, &r.deployed_by, commit, hash, nodes, r.status()]);
             }
+            table.printstd();
             process::exit(0);
         }
         TopLevelCmds::Init { mut name } => {
             let conf_path = Path::new(PROJ_JSON_CONFIG);
             if conf_path.exists() {
-                println!("Current directory is already a project!");
-                process::exit(1);
+                output::fail("Current directory is already a project!");
             }
 
             if name.len() == 0 {
+                require_interactive(non_interactive, "Use `--name` to name the project.");
                 name = crypt_utils::prompt("Please name this project: ");
             }
 
@@ -342,102 +891,533 @@ fn main() {
             process::exit(0);
         },
         TopLevelCmds::ListNodes => {
-            let gstate = handle_gstate(&gstate);
+            let mut gstate = handle_gstate(&gstate, &profile, &auth_endpoint);
 
-            let mut table = Table::new();
-
-            table.set_titles(row!["Name", "Ip Addr", "Status"]);
+            let clients = gstate.agent_clients();
+            let status_map = rt.block_on(agent_interface::check_node_states(&clients));
+            if let Err(e) = gstate.pin_observed_certs(&clients) {
+                output::error(&format!("Unable to save the nodes' certificates -> {}", e));
+            }
 
-            let status_map = rt.block_on(agent_interface::check_node_states(&gstate))
-                .into_iter()
-                .collect::<HashMap<String, bool>>();
+            let nodes = gstate.nodes
+                .iter()
+                .map(|n| output::NodeStatus {
+                    name: n.name.clone(),
+                    node_id: n.node_id.clone(),
+                    addr: n.addr.clone(),
+                    active: *status_map.get(&n.node_id).unwrap_or(&false),
+                })
+                .collect::<Vec<output::NodeStatus>>();
 
-            for n  in gstate.nodes.iter() {
-                let status = if *(status_map.get(&n.node_id).unwrap_or(&false)){
-                    "active"
+            output::print(format, &nodes, || {
+                let mut table = Table::new();
+                table.set_titles(row!["Name", "Ip Addr", "Status"]);
+                for n in nodes.iter() {
+                    let status = if n.active { "active" } else { "inactive" };
+                    table.add_row(row![&n.name, &n.addr, status]);
                 }
-                else {
-                    "inactive"
-                };
-                table.add_row(row![&n.name, &n.addr, status]);
-            }
-            table.printstd();
+                table.printstd();
+            });
             process::exit(0);
         }
         TopLevelCmds::ListProjects { mut name } => {
-            let gstate = handle_gstate(&gstate);
+            let mut gstate = handle_gstate(&gstate, &profile, &auth_endpoint);
 
-            let mut node = &gstate.nodes[0];
-            if name.len() == 0 {
-                node = prompt_node(&gstate); 
+            let node = if name.len() == 0 {
+                if format.is_machine() {
+                    output::fail("Use `--name` to choose a node.");
+                }
+                require_interactive(non_interactive, "Use `--name` to choose a node.");
+                let node = prompt_node(&gstate).clone();
                 name = node.name.clone();
                 println!("\n\n");
+                node
             }
             else {
-                let mut found_node = false;
-                for n in gstate.nodes.iter() {
-                    if n.name == name {
-                        found_node = true;
-                        node = n;
-                    }
+                find_node(&gstate, &name).clone()
+            };
+
+
+            let res = match gstate.agent_client(&node) {
+                Ok(c) => {
+                    let res = rt.block_on(c.list_projects());
+                    gstate.pin_observed_certs(&[c]).ok();
+                    res
+                },
+                Err(e) => Err(e),
+            };
+            let projects = match res {
+                Ok(v) => output::NodeProjects { node: name.clone(), projects: v },
+                Err(e) => output::fail(format!("Error fetching project data from node `{}` -> {}", name, e.report())),
+            };
+            output::print(format, &projects, || {
+                println!("PROJECTS:");
+                if projects.projects.len() == 0 {
+                    println!("None");
                 }
-                if !found_node {
-                    println!("No node named `{}`", name);
-                    process::exit(0);
+                for (i, p) in projects.projects.iter().enumerate() {
+                    println!("{:2<})\t{}", i, p);
                 }
-            }
+            });
+            process::exit(0);
+        }
+        TopLevelCmds::Containers { nodes } => {
+            let mut gstate = handle_gstate(&gstate, &profile, &auth_endpoint);
+            let clients = clients_for(&gstate, &nodes, format);
 
+            let listings = clients
+                .iter()
+                .map(|c| {
+                    let res = rt.block_on(c.list_container_stats_all()).map_err(|e| e.report());
+                    output::NodeContainers::new(&c.node().name, res)
+                })
+                .collect::<Vec<output::NodeContainers>>();
+            gstate.pin_observed_certs(&clients).ok();
 
-            let f = agent_interface::list_projects(&node.addr, &gstate.tyb_key);
-            let res = rt.block_on(f);
-            match res {
-                Ok(v) =>  {
-                    println!("PROJECTS:");
-                    if v.len() == 0 {
-                        println!("None");
+            output::print(format, &listings, || {
+                let mut table = Table::new();
+                table.set_titles(row!["Node", "Name", "Image", "Status", "CPU", "Memory", "Ports"]);
+                for l in listings.iter() {
+                    if let Some(ref e) = l.error {
+                        table.add_row(row![&l.node, format!("error -> {}", e), "", "", "", "", ""]);
                     }
-                    for (i, p) in v.iter().enumerate() {
-                        println!("{:2<})\t{}", i, p);
+                    for c in l.containers.iter() {
+                        let field = |k: &str| c.get(k).cloned().unwrap_or("-".to_string());
+                        table.add_row(row![&l.node, field("names"), field("image"), field("status"), field("cpu_perc"), field("mem_usage_limit"), field("ports")]);
                     }
                 }
-                Err(e) => {
-                    println!("Error fetching project data from node `{}` -> {:?}", name, e);
+                table.printstd();
+            });
+            let failed = listings.iter().any(|l| l.error.is_some());
+            process::exit(if failed { 1 } else { 0 });
+        }
+        TopLevelCmds::Diags { nodes } => {
+            let mut gstate = handle_gstate(&gstate, &profile, &auth_endpoint);
+            let clients = clients_for(&gstate, &nodes, format);
+
+            let diags = clients
+                .iter()
+                .map(|c| {
+                    let (diagnostics, error) = match rt.block_on(c.get_diags()) {
+                        Ok(d) => (Some(d), None),
+                        Err(e) => (None, Some(e.report())),
+                    };
+                    output::NodeDiagnostics { node: c.node().name.clone(), diagnostics, error }
+                })
+                .collect::<Vec<output::NodeDiagnostics>>();
+            gstate.pin_observed_certs(&clients).ok();
+
+            output::print(format, &diags, || {
+                for d in diags.iter() {
+                    println!("{}", Style::new().bold().paint(format!("Node `{}`", d.node)));
+                    if let Some(ref e) = d.error {
+                        println!("  error -> {}", e);
+                    }
+                    // Listed field by field so new diagnostics show up without changes here
+                    let fields = d.diagnostics
+                        .as_ref()
+                        .and_then(|d| serde_json::to_value(d).ok())
+                        .and_then(|v| v.as_object().cloned())
+                        .unwrap_or_default();
+                    let mut table = Table::new();
+                    for (k, v) in fields.iter() {
+                        let v = v.as_str().map(|s| s.to_string()).unwrap_or(v.to_string());
+                        table.add_row(row![k, v]);
+                    }
+                    table.printstd();
                 }
-            }
-            process::exit(0);
+            });
+            let failed = diags.iter().any(|d| d.error.is_some());
+            process::exit(if failed { 1 } else { 0 });
         }
         TopLevelCmds::AddUpstream { mut name } => {
-            let gstate = handle_gstate(&gstate);
+            let (mut conf, _) = read_proj_config(None, format);
+            let gstate = handle_gstate(&gstate, &profile, &auth_endpoint);
 
             if name.len() == 0 {
+                require_interactive(non_interactive, "Use `--name` to choose a node.");
                 let node = prompt_node(&gstate);
                 name = node.name.clone();
             }
 
-            let conf = fs::read_to_string(PROJ_JSON_CONFIG)
-                .expect("Error, not a valid tynkerbase project");
-            let mut conf: ProjConfig = serde_json::from_str(&conf).unwrap();
             if !conf.parse_name() {
-                println!("Warning, project name must adhere to docker's naming conventions: \
-                Changing the name to `{}`", &conf.proj_name);
+                output::note(format, format!("Warning, project name must adhere to docker's naming conventions: \
+                Changing the name to `{}`", &conf.proj_name));
             }
 
             if conf.node_names.contains(&name) {
-                println!("Node is already set as an upstream target.");
+                output::note(format, "Node is already set as an upstream target.");
                 process::exit(0);
             }
             conf.node_names.push(name);
-            let conf = serde_json::to_string_pretty(&conf).unwrap();
-            fs::write(PROJ_JSON_CONFIG, conf)
+            config::save_proj_config(PROJ_JSON_CONFIG, &conf)
                 .expect("Unable to write to config file.");
             process::exit(0);
         },
+        TopLevelCmds::Profile { command } => {
+            match command {
+                ProfileCmds::List => {
+                    let active = global_state::active_profile();
+                    let profiles = global_state::list_profiles()
+                        .into_iter()
+                        .map(|p| output::Profile { active: p == active, name: p })
+                        .collect::<Vec<output::Profile>>();
+                    output::print(format, &profiles, || {
+                        if profiles.len() == 0 {
+                            println!("No profiles found. Use `tyb login` to create one.");
+                        }
+                        for p in profiles.iter() {
+                            let marker = if p.active { "*" } else { " " };
+                            println!("{} {}", marker, p.name);
+                        }
+                    });
+                },
+                ProfileCmds::Use { name } => {
+                    if !GlobalState::exists(&name) {
+                        output::fail(format!("No profile named `{}`. Log in with `tyb --profile {} login` first.", name, name));
+                    }
+                    if let Err(e) = global_state::set_active_profile(&name) {
+                        output::fail(e);
+                    }
+                    output::note(format, format!("Switched to profile `{}`", name));
+                },
+                ProfileCmds::Remove { name } => {
+                    if let Err(e) = global_state::remove_profile(&name) {
+                        output::fail(e);
+                    }
+                    output::note(format, format!("Removed profile `{}`", name));
+                },
+            }
+            process::exit(0);
+        },
+        TopLevelCmds::Node { command } => {
+            let mut gstate = handle_gstate(&gstate, &profile, &auth_endpoint);
+            match command {
+                NodeCmds::Trust { name } => {
+                    let node = find_node(&gstate, &name).clone();
+
+                    // Connect without a pin so we can see what the node presents now
+                    let client = AgentClient::new(&node, gstate.api_key(), None).unwrap();
+                    let res = rt.block_on(client.ping());
+                    let fingerprint = match client.observed_cert() {
+                        Some(f) => f,
+                        None => {
+                            match res {
+                                Err(e) => output::fail(format!("Unable to reach node `{}` -> {}", name, e.report())),
+                                Ok(_) => output::fail(format!("Node `{}` isn't served over https, there is no certificate to trust.", name)),
+                            }
+                        }
+                    };
+
+                    let previous = gstate.cert_pins.get(&node.node_id).cloned();
+                    if previous.as_ref() == Some(&fingerprint) {
+                        output::note(format, format!("The certificate of node `{}` is already trusted.", name));
+                        process::exit(0);
+                    }
+                    if let Some(previous) = previous {
+                        output::note(format, format!("Previously trusted fingerprint: {}", display_fingerprint(&previous)));
+                    }
+                    output::note(format, format!("Fingerprint presented by `{}`:  {}", name, display_fingerprint(&fingerprint)));
+
+                    require_interactive(non_interactive, "Run `tyb node trust` from a terminal to check the new certificate.");
+                    let answer = crypt_utils::prompt("Trust this certificate? [y/N]: ");
+                    if answer.trim().to_lowercase() != "y" {
+                        output::fail("Certificate not trusted.");
+                    }
+                    gstate.cert_pins.insert(node.node_id.clone(), fingerprint);
+                    if let Err(e) = gstate.save() {
+                        output::fail(e);
+                    }
+                    output::note(format, format!("Trusted the certificate of node `{}`", name));
+                },
+                NodeCmds::ForgetCert { name } => {
+                    let node_id = find_node(&gstate, &name).node_id.clone();
+                    if gstate.cert_pins.remove(&node_id).is_none() {
+                        output::note(format, format!("No certificate is pinned for node `{}`", name));
+                        process::exit(0);
+                    }
+                    if let Err(e) = gstate.save() {
+                        output::fail(e);
+                    }
+                    output::note(format, format!("Forgot the certificate of node `{}`", name));
+                },
+            }
+            process::exit(0);
+        },
+        TopLevelCmds::Container { command } => {
+            let mut gstate = handle_gstate(&gstate, &profile, &auth_endpoint);
+            let (action, target) = match command {
+                ContainerCmds::Stop(t) => (ContainerAction::Stop, t),
+                ContainerCmds::Start(t) => (ContainerAction::Start, t),
+                ContainerCmds::Restart(t) => (ContainerAction::Restart, t),
+                ContainerCmds::Rm(t) => (ContainerAction::Remove, t),
+            };
+
+            let all_nodes = target.nodes.len() == 0;
+            let clients = clients_for(&gstate, &target.nodes, format);
+
+            let mut results = vec![];
+            for client in clients.iter() {
+                let error = match rt.block_on(client.container_action(&target.project, action)) {
+                    Ok(_) => None,
+                    // When no nodes were given, nodes without the project aren't an error
+                    Err(TynkerError::ProjectNotFound(_)) if all_nodes => continue,
+                    Err(e) => Some(e.report()),
+                };
+                results.push(output::ContainerActionResult {
+                    node: client.node().name.clone(),
+                    project: target.project.clone(),
+                    action: action.as_str(),
+                    error,
+                });
+            }
+
+            if let Err(e) = gstate.pin_observed_certs(&clients) {
+                output::note(format, format!("WARNING: unable to save node certificates -> {}", e));
+            }
+            if all_nodes && results.len() == 0 {
+                output::fail(format!("Project `{}` isn't deployed on any node.", &target.project));
+            }
+
+            output::print(format, &results, || {
+                for r in results.iter() {
+                    match r.error {
+                        None => println!("Node `{}`: {} `{}`", r.node, action.past_tense(), r.project),
+                        Some(ref e) => println!("Node `{}`: failed to {} `{}` -> {}", r.node, r.action, r.project, e),
+                    }
+                }
+            });
+            let failed = results.iter().any(|r| r.error.is_some());
+            process::exit(if failed { 1 } else { 0 });
+        },
+        TopLevelCmds::Logs { project, nodes, follow, since, tail } => {
+            let mut gstate = handle_gstate(&gstate, &profile, &auth_endpoint);
+
+            let all_nodes = nodes.len() == 0;
+            let clients = clients_for(&gstate, &nodes, format);
+            let opts = LogOptions { follow, since, tail };
+
+            // Stream from every node at once, prefixing lines when there's more than one
+            let prefix_logs = clients.len() > 1;
+            let mut handles = vec![];
+            for c in clients.iter() {
+                let client = c.clone();
+                let project = project.clone();
+                let opts = opts.clone();
+                let node_name = c.node().name.clone();
+                let handle = rt.spawn(async move {
+                    client.stream_logs(&project, &opts, |line| {
+                        let log = output::LogLine { node: node_name.clone(), line: line.to_string() };
+                        output::stream(format, &log, || {
+                            if prefix_logs {
+                                println!("{} {}", Blue.bold().paint(format!("[{}]", node_name)), line);
+                            }
+                            else {
+                                println!("{}", line);
+                            }
+                        });
+                    }).await
+                });
+                handles.push((handle, c.node()));
+            }
+
+            let mut failed = false;
+            let mut found = false;
+            for (handle, node) in handles {
+                let res = rt.block_on(handle)
+                    .unwrap_or_else(|e| Err(TynkerError::Other(format!("Log task failed -> {}", e))));
+                match res {
+                    Ok(_) => found = true,
+                    Err(TynkerError::ProjectNotFound(_)) if all_nodes => {}
+                    Err(e) => {
+                        output::error(format!("Node `{}`: unable to get logs -> {}", node.name, e.report()));
+                        failed = true;
+                    }
+                }
+            }
+
+            if let Err(e) = gstate.pin_observed_certs(&clients) {
+                output::note(format, format!("WARNING: unable to save node certificates -> {}", e));
+            }
+            if all_nodes && !found && !failed {
+                output::fail(format!("Project `{}` isn't deployed on any node.", &project));
+            }
+            process::exit(if failed { 1 } else { 0 });
+        },
+        TopLevelCmds::Exec { project, node, tty, cmd } => {
+            let mut gstate = handle_gstate(&gstate, &profile, &auth_endpoint);
+
+            let node = match node {
+                Some(ref name) => find_node(&gstate, name).clone(),
+                None if gstate.nodes.len() == 1 => gstate.nodes[0].clone(),
+                None => {
+                    require_interactive(non_interactive, "Use `--node` to choose a node.");
+                    prompt_node(&gstate).clone()
+                }
+            };
+            let client = match gstate.agent_client(&node) {
+                Ok(c) => c,
+                Err(e) => output::fail(format!("Unable to create a client for node `{}` -> {}", node.name, e.report())),
+            };
+
+            let res = rt.block_on(exec::run(&client, &project, &cmd, tty));
+            if let Err(e) = gstate.pin_observed_certs(&[client]) {
+                output::note(format, format!("WARNING: unable to save node certificates -> {}", e));
+            }
+            match res {
+                Ok(code) => process::exit(code),
+                Err(e) => output::fail(format!("Error running command on node `{}` -> {}", node.name, e.report())),
+            }
+        },
+        TopLevelCmds::Config { command } => {
+            let ConfigCmds::Validate { environment, skip_nodes } = command;
+
+            let nodes = match (&gstate, skip_nodes) {
+                (_, true) => None,
+                (Some(gs), false) => Some(gs.nodes.iter().map(|n| n.name.clone()).collect::<Vec<String>>()),
+                (None, false) => {
+                    output::note(format, "Not logged in, skipping node checks. Use `--skip-nodes` to silence this.");
+                    None
+                }
+            };
+
+            let envs = match environment {
+                Some(env) => vec![Some(env)],
+                None => std::iter::once(None)
+                    .chain(config::list_envs(PROJ_JSON_CONFIG).into_iter().map(Some))
+                    .collect(),
+            };
+
+            let mut issues: Vec<config::Issue> = vec![];
+            for env in envs.iter() {
+                for issue in config::validate(PROJ_JSON_CONFIG, env.as_deref(), nodes.as_deref()) {
+                    // Problems in the base config show up once per environment
+                    if issues.iter().any(|i| i.to_string() == issue.to_string()) {
+                        continue;
+                    }
+                    issues.push(issue);
+                }
+            }
+
+            let checked = envs
+                .iter()
+                .map(|e| match e {
+                    Some(env) => config::overlay_path(PROJ_JSON_CONFIG, env),
+                    None => PROJ_JSON_CONFIG.to_string(),
+                })
+                .collect::<Vec<String>>();
+            let report = output::ConfigReport {
+                checked,
+                errors: issues.iter().filter(|i| i.severity == config::Severity::Error).count(),
+                warnings: issues.iter().filter(|i| i.severity == config::Severity::Warning).count(),
+                issues,
+            };
+            output::print(format, &report, || {
+                for issue in report.issues.iter() {
+                    println!("{}", issue);
+                }
+                println!("Checked {}: {} error(s), {} warning(s)", report.checked.join(", "), report.errors, report.warnings);
+            });
+            process::exit(if report.errors > 0 { 1 } else { 0 });
+        },
+        TopLevelCmds::Env { command } => {
+            let (mut conf, deploy_conf) = read_proj_config(None, format);
+            conf.parse_name();
+
+            match command {
+                EnvCmds::Set { vars } => {
+                    let mut pairs = vec![];
+                    for var in vars.iter() {
+                        let Some((key, value)) = var.split_once('=') else {
+                            output::fail(format!("`{}` must be in the form KEY=VALUE", var));
+                        };
+                        if let Err(e) = secrets::validate_key(key) {
+                            output::fail(e);
+                        }
+                        pairs.push((key, value));
+                    }
+
+                    let project_secrets = secrets::load(&conf.proj_name).unwrap_or_default();
+                    for (key, value) in pairs {
+                        if let Err(e) = config::set_env(PROJ_JSON_CONFIG, key, Some(value)) {
+                            output::fail(e);
+                        }
+                        if project_secrets.contains_key(key) {
+                            output::note(format, format!("WARNING: `{}` is also set as a secret, the secret takes precedence", key));
+                        }
+                    }
+                },
+                EnvCmds::Unset { keys } => {
+                    for key in keys.iter() {
+                        if let Err(e) = config::set_env(PROJ_JSON_CONFIG, key, None) {
+                            output::fail(e);
+                        }
+                    }
+                },
+                EnvCmds::List { show_secrets } => {
+                    let project_secrets = match secrets::load(&conf.proj_name) {
+                        Ok(s) => s,
+                        Err(e) => output::fail(format!("Error loading secrets -> {}", e)),
+                    };
+
+                    let mut vars = vec![];
+                    for (key, value) in deploy_conf.env.iter() {
+                        if !project_secrets.contains_key(key) {
+                            vars.push(output::EnvVar { name: key.clone(), value: value.clone(), source: "config" });
+                        }
+                    }
+                    for (key, value) in project_secrets.iter() {
+                        let value = if show_secrets { value.clone() } else { "********".to_string() };
+                        vars.push(output::EnvVar { name: key.clone(), value, source: "secret" });
+                    }
+                    output::print(format, &vars, || {
+                        let mut table = Table::new();
+                        table.set_titles(row!["Name", "Value", "Source"]);
+                        for v in vars.iter() {
+                            table.add_row(row![&v.name, &v.value, v.source]);
+                        }
+                        table.printstd();
+                    });
+                },
+            }
+            process::exit(0);
+        },
+        TopLevelCmds::Secret { command } => {
+            let (mut conf, _) = read_proj_config(None, format);
+            conf.parse_name();
+
+            let mut project_secrets = match secrets::load(&conf.proj_name) {
+                Ok(s) => s,
+                Err(e) => output::fail(format!("Error loading secrets -> {}", e)),
+            };
+
+            match command {
+                SecretCmds::Set { key } => {
+                    if let Err(e) = secrets::validate_key(&key) {
+                        output::fail(e);
+                    }
+                    require_interactive(non_interactive, "Secrets are only read from a prompt.");
+                    let value = crypt_utils::prompt_secret(&format!("Value of `{}`: ", &key));
+                    project_secrets.insert(key, value);
+                },
+                SecretCmds::Unset { keys } => {
+                    for key in keys.iter() {
+                        if project_secrets.remove(key).is_none() {
+                            output::note(format, format!("No secret named `{}`", key));
+                        }
+                    }
+                },
+            }
+
+            if let Err(e) = secrets::save(&conf.proj_name, &project_secrets) {
+                output::fail(format!("Error saving secrets -> {}", e));
+            }
+            process::exit(0);
+        },
         TopLevelCmds::Help => {
             // Do nothing here
         }
-        _ => {
-            println!("Command not supported.")
-        }
+        _ => output::fail("Command not supported."),
     }
 
     let tynker = vec![10, 32, 95, 95, 95, 95, 95, 95, 95, 95, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 95, 95, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 10, 124, 32, 32, 32, 32, 32, 32, 32, 32, 92, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 124, 32, 32, 92, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 10, 32, 92, 36, 36, 36, 36, 36, 36, 36, 36, 95, 95, 32, 32, 32, 32, 95, 95, 32, 32, 95, 95, 95, 95, 95, 95, 95, 32, 32, 124, 32, 36, 36, 32, 32, 32, 95, 95, 32, 32, 32, 95, 95, 95, 95, 95, 95, 32, 32, 32, 32, 95, 95, 95, 95, 95, 95, 32, 32, 10, 32, 32, 32, 124, 32, 36, 36, 32, 32, 124, 32, 32, 92, 32, 32, 124, 32, 32, 92, 124, 32, 32, 32, 32, 32, 32, 32, 92, 32, 124, 32, 36, 36, 32, 32, 47, 32, 32, 92, 32, 47, 32, 32, 32, 32, 32, 32, 92, 32, 32, 47, 32, 32, 32, 32, 32, 32, 92, 32, 10, 32, 32, 32, 124, 32, 36, 36, 32, 32, 124, 32, 36, 36, 32, 32, 124, 32, 36, 36, 124, 32, 36, 36, 36, 36, 36, 36, 36, 92, 124, 32, 36, 36, 95, 47, 32, 32, 36, 36, 124, 32, 32, 36, 36, 36, 36, 36, 36, 92, 124, 32, 32, 36, 36, 36, 36, 36, 36, 92, 10, 32, 32, 32, 124, 32, 36, 36, 32, 32, 124, 32, 36, 36, 32, 32, 124, 32, 36, 36, 124, 32, 36, 36, 32, 32, 124, 32, 36, 36, 124, 32, 36, 36, 32, 32, 32, 36, 36, 32, 124, 32, 36, 36, 32, 32, 32, 32, 36, 36, 124, 32, 36, 36, 32, 32, 32, 92, 36, 36, 10, 32, 32, 32, 124, 32, 36, 36, 32, 32, 124, 32, 36, 36, 95, 95, 47, 32, 36, 36, 124, 32, 36, 36, 32, 32, 124, 32, 36, 36, 124, 32, 36, 36, 36, 36, 36, 36, 92, 32, 124, 32, 36, 36, 36, 36, 36, 36, 36, 36, 124, 32, 36, 36, 32, 32, 32, 32, 32, 32, 10, 32, 32, 32, 124, 32, 36, 36, 32, 32, 32, 92, 36, 36, 32, 32, 32, 32, 36, 36, 124, 32, 36, 36, 32, 32, 124, 32, 36, 36, 124, 32, 36, 36, 32, 32, 92, 36, 36, 92, 32, 92, 36, 36, 32, 32, 32, 32, 32, 92, 124, 32, 36, 36, 32, 32, 32, 32, 32, 32, 10, 32, 32, 32, 32, 92, 36, 36, 32, 32, 32, 95, 92, 36, 36, 36, 36, 36, 36, 36, 32, 92, 36, 36, 32, 32, 32, 92, 36, 36, 32, 92, 36, 36, 32, 32, 32, 92, 36, 36, 32, 32, 92, 36, 36, 36, 36, 36, 36, 36, 32, 92, 36, 36, 32, 32, 32, 32, 32, 32, 10, 32, 32, 32, 32, 32, 32, 32, 32, 32, 124, 32, 32, 92, 95, 95, 124, 32, 36, 36, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 10, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 92, 36, 36, 32, 32, 32, 32, 36, 36, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 10, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 92, 36, 36, 36, 36, 36, 36, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 10];
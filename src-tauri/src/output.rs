@@ -0,0 +1,197 @@
+//! Prints command results either for people (tables) or for scripts (`--output json|yaml`).
+//! The structs here are what scripts see, so their field names shouldn't change.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::process;
+
+use clap::ValueEnum;
+use serde::Serialize;
+use tynkerbase_universal::netwk_utils::NodeDiags;
+use tynkerbase_client::config::Issue;
+use tynkerbase_client::history::DeployRecord;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    #[default]
+    Table,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    /// Whether the output is meant for scripts, in which case only the result goes to stdout.
+    pub fn is_machine(&self) -> bool {
+        *self != Format::Table
+    }
+}
+
+/// Prints `value` as json or yaml, or calls `table` to print it for people.
+pub fn print<T: Serialize + ?Sized>(format: Format, value: &T, table: impl FnOnce()) {
+    match format {
+        Format::Table => table(),
+        Format::Json => {
+            let s = serde_json::to_string_pretty(value)
+                .expect("If you're seeing this error, send out a bug report.");
+            println!("{}", s);
+        },
+        Format::Yaml => {
+            let s = serde_yaml::to_string(value)
+                .expect("If you're seeing this error, send out a bug report.");
+            print!("{}", s);
+        },
+    }
+}
+
+/// Prints one record of a stream as soon as it's available: a json object per line for json,
+/// a yaml document per record for yaml, or calls `table` to print it for people.
+pub fn stream<T: Serialize + ?Sized>(format: Format, value: &T, table: impl FnOnce()) {
+    match format {
+        Format::Table => table(),
+        Format::Json => {
+            let s = serde_json::to_string(value)
+                .expect("If you're seeing this error, send out a bug report.");
+            println!("{}", s);
+        },
+        Format::Yaml => {
+            let s = serde_yaml::to_string(value)
+                .expect("If you're seeing this error, send out a bug report.");
+            print!("---\n{}", s);
+        },
+    }
+}
+
+/// Prints a message that isn't part of the result. It goes to stderr for json and yaml so
+/// stdout stays parseable.
+pub fn note(format: Format, msg: impl Display) {
+    if format.is_machine() {
+        eprintln!("{}", msg);
+    }
+    else {
+        println!("{}", msg);
+    }
+}
+
+/// Prints an error to stderr, whatever the format, without stopping the command.
+pub fn error(msg: impl Display) {
+    eprintln!("{}", msg);
+}
+
+/// Prints `msg` like `error` and exits with 1.
+pub fn fail(msg: impl Display) -> ! {
+    error(msg);
+    process::exit(1);
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeStatus {
+    pub name: String,
+    pub node_id: String,
+    pub addr: String,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeProjects {
+    pub node: String,
+    pub projects: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeContainers {
+    pub node: String,
+    /// Fields as reported by the agent, sorted by name.
+    pub containers: Vec<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl NodeContainers {
+    pub fn new(node: &str, res: Result<Vec<HashMap<String, String>>, String>) -> Self {
+        match res {
+            Ok(c) => NodeContainers {
+                node: node.to_string(),
+                containers: c.into_iter().map(|c| c.into_iter().collect()).collect(),
+                error: None,
+            },
+            Err(e) => NodeContainers { node: node.to_string(), containers: vec![], error: Some(e) },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct NodeDiagnostics {
+    pub node: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diagnostics: Option<NodeDiags>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeploySummary<'a> {
+    #[serde(flatten)]
+    pub record: &'a DeployRecord,
+    /// `succeeded`, `partial` or `failed`, same as in `tyb history`.
+    pub status: &'static str,
+    pub failures: &'a [String],
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RollbackResult {
+    pub node: String,
+    /// Release the node runs after rolling back.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RollbackResult {
+    pub fn new(node: &str, res: Result<String, String>) -> Self {
+        match res {
+            Ok(v) => RollbackResult { node: node.to_string(), version: Some(v), error: None },
+            Err(e) => RollbackResult { node: node.to_string(), version: None, error: Some(e) },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContainerActionResult {
+    pub node: String,
+    pub project: String,
+    /// `stop`, `start`, `restart` or `remove`.
+    pub action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    pub node: String,
+    pub line: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EnvVar {
+    pub name: String,
+    /// `********` for secrets unless they're shown.
+    pub value: String,
+    /// `config` or `secret`.
+    pub source: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Profile {
+    pub name: String,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigReport {
+    /// Config files that were checked, the base config and each overlay.
+    pub checked: Vec<String>,
+    pub errors: usize,
+    pub warnings: usize,
+    pub issues: Vec<Issue>,
+}
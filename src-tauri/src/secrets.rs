@@ -0,0 +1,68 @@
+use crate::{config, consts};
+use crate::vault::{self, SealedBox};
+use anyhow::{anyhow, Result};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+};
+
+/// Environment variables holding secrets, keyed by name.
+pub type Secrets = BTreeMap<String, String>;
+
+pub fn dir() -> String {
+    format!("{}/secrets", consts::app_data())
+}
+
+/// Secrets are sealed with the local vault key and kept in the app data directory, so they
+/// never end up in `tynkerbase-config.json` or the project's repository.
+pub fn path(project: &str) -> Result<String> {
+    config::validate_project_name(project)?;
+    Ok(format!("{}/{}.bin", dir(), project))
+}
+
+pub fn load(project: &str) -> Result<Secrets> {
+    let path = path(project)?;
+    if !Path::new(&path).exists() {
+        return Ok(Secrets::new());
+    }
+    let bytes = fs::read(&path)
+        .map_err(|e| anyhow!("Error reading from file `{}` -> {}", &path, e))?;
+    let sealed: SealedBox = bincode::deserialize(&bytes)
+        .map_err(|e| anyhow!("Secrets file `{}` is corrupted -> {}", &path, e))?;
+    let plaintext = vault::open_bytes(&sealed)?;
+
+    bincode::deserialize(&plaintext)
+        .map_err(|e| anyhow!("Failed to deserialize secrets -> {}", e))
+}
+
+pub fn save(project: &str, secrets: &Secrets) -> Result<()> {
+    let path = path(project)?;
+    let dir = dir();
+    if !Path::new(&dir).exists() {
+        fs::create_dir_all(&dir)
+            .map_err(|e| anyhow!("Error creating secrets directory -> {}", e))?;
+    }
+
+    let plaintext = bincode::serialize(secrets)
+        .map_err(|e| anyhow!("Error serializing secrets -> {}", e))?;
+    let sealed = vault::seal_bytes(plaintext)?;
+    let bytes = bincode::serialize(&sealed)
+        .map_err(|e| anyhow!("Error serializing secrets -> {}", e))?;
+
+    fs::write(path, bytes)
+        .map_err(|e| anyhow!("Error saving secrets to file -> {}", e))
+}
+
+/// Environment variable names must be valid shell identifiers.
+pub fn validate_key(key: &str) -> Result<()> {
+    let mut chars = key.chars();
+    let valid = match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    };
+    if !valid {
+        return Err(anyhow!("`{}` is not a valid environment variable name", key));
+    }
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use crate::consts::{self, AUTH_ENDPOINT_ENV, DEFAULT_AUTH_ENDPOINT};
+use serde::{Serialize, Deserialize};
+use anyhow::{anyhow, Result};
+use reqwest::Url;
+use std::{
+    collections::HashMap,
+    env,
+    fs,
+    path::Path,
+};
+
+/// User editable client settings, stored as `settings.json` in the app data directory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Settings {
+    #[serde(default)]
+    pub profiles: HashMap<String, ProfileSettings>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProfileSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_endpoint: Option<String>,
+}
+
+impl Settings {
+    pub fn path() -> String {
+        format!("{}/settings.json", consts::app_data())
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = Self::path();
+        if !Path::new(&path).exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Error reading from file `{}` -> {}", &path, e))?;
+        serde_json::from_str(&text)
+            .map_err(|e| anyhow!("Error parsing settings file `{}` -> {}", &path, e))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let app_data = consts::app_data();
+        if !Path::new(&app_data).exists() {
+            fs::create_dir_all(&app_data).unwrap();
+        }
+        let text = serde_json::to_string_pretty(self)
+            .map_err(|e| anyhow!("Error serializing settings -> {}", e))?;
+        fs::write(Self::path(), text)
+            .map_err(|e| anyhow!("Error saving settings to file -> {}", e))
+    }
+
+    pub fn profile(&self, profile: &str) -> ProfileSettings {
+        self.profiles.get(profile).cloned().unwrap_or_default()
+    }
+}
+
+/// Resolves the auth server for a profile. In order of precedence: the `--auth-endpoint` 
+/// flag, the `TYB_AUTH_ENDPOINT` environment variable, the profile's entry in 
+/// `settings.json` and finally the hosted server.
+pub fn auth_endpoint(profile: &str, flag: Option<&str>) -> Result<String> {
+    if let Some(endpoint) = flag {
+        return validate_endpoint(endpoint);
+    }
+    if let Ok(endpoint) = env::var(AUTH_ENDPOINT_ENV) {
+        return validate_endpoint(&endpoint)
+            .map_err(|e| anyhow!("Invalid `{}` -> {}", AUTH_ENDPOINT_ENV, e));
+    }
+    if let Some(endpoint) = Settings::load()?.profile(profile).auth_endpoint {
+        return validate_endpoint(&endpoint)
+            .map_err(|e| anyhow!("Invalid auth endpoint for profile `{}` in `{}` -> {}", profile, Settings::path(), e));
+    }
+    Ok(DEFAULT_AUTH_ENDPOINT.to_string())
+}
+
+/// Checks that `endpoint` is an absolute http(s) url and strips any trailing slash.
+pub fn validate_endpoint(endpoint: &str) -> Result<String> {
+    let url = Url::parse(endpoint)
+        .map_err(|e| anyhow!("`{}` is not a valid url: {}", endpoint, e))?;
+
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(anyhow!("`{}` must use http or https", endpoint));
+    }
+    if url.host_str().is_none() {
+        return Err(anyhow!("`{}` is missing a host", endpoint));
+    }
+
+    Ok(endpoint.trim_end_matches('/').to_string())
+}
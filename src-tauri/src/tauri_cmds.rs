@@ -1,30 +1,23 @@
-use tauri::{self, State, InvokeError};
+use tauri::{self, State, InvokeError, Window};
+use serde::Serialize;
 use tynkerbase_universal::crypt_utils;
 use tynkerbase_universal::netwk_utils::{self, Node, NodeDiags};
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Mutex as TkMutex;
-use crate::global_state::GlobalState;
-use crate::agent_interface;
-use crate::api_auth_interface;
+use tokio::{sync::Mutex as TkMutex, task::AbortHandle};
+use tynkerbase_client::global_state::{self, GlobalState};
+use tynkerbase_client::agent_interface::{self, AgentClient, ContainerAction, LogOptions};
+use tynkerbase_client::api_auth_interface;
+use tynkerbase_client::error;
+use tynkerbase_client::history::{self, DeployRecord};
 
 
 #[tauri::command]
 pub async fn ping(node_id: &str, state: State<'_, Arc<TkMutex<GlobalState>>>) -> Result<bool, InvokeError> {
-    let lock = state.lock().await;
-    let tyb_key = lock.tyb_key.clone();
-    let mut node = None;
-    for n in lock.nodes.iter() {
-        if n.node_id == node_id {
-            node = Some(n.clone());
-            break;
-        }
-    }
-    drop(lock);
-
-    if let Some(node) = node {
-        let f = agent_interface::ping(node.addr);
-        return Ok(f.await.is_ok());
+    if let Some((_, Ok(client))) = query_client(node_id, &state).await {
+        let res = client.ping().await.is_ok();
+        pin_observed_cert(client, &state).await;
+        return Ok(res);
     }
 
     Ok(false)
@@ -32,9 +25,9 @@ pub async fn ping(node_id: &str, state: State<'_, Arc<TkMutex<GlobalState>>>) ->
 
 #[tauri::command]
 pub async fn login_account(email: &str, password: &str, state: State<'_, Arc<TkMutex<GlobalState>>>) -> Result<(), InvokeError> {
-    let res = api_auth_interface::login(email, password).await;
+    let res = api_auth_interface::login(&auth_endpoint(&state).await, email, password).await;
     if let Err(e) = res {
-        return Err(InvokeError::from(format!("Error -> {}", e)));
+        return Err(InvokeError::from(e));
     }
 
     Ok(())
@@ -42,9 +35,9 @@ pub async fn login_account(email: &str, password: &str, state: State<'_, Arc<TkM
 
 #[tauri::command]
 pub async fn create_account(email: &str, password: &str, state: State<'_, Arc<TkMutex<GlobalState>>>) -> Result<(), InvokeError> {
-    let res = api_auth_interface::create_account(email, password).await;
+    let res = api_auth_interface::create_account(&auth_endpoint(&state).await, email, password).await;
     if let Err(e) = res {
-        return Err(InvokeError::from(format!("Error -> {}", e)));
+        return Err(InvokeError::from(e));
     }
 
     Ok(())
@@ -52,9 +45,9 @@ pub async fn create_account(email: &str, password: &str, state: State<'_, Arc<Tk
 
 #[tauri::command]
 pub async fn delete_account(email: &str, password: &str, state: State<'_, Arc<TkMutex<GlobalState>>>) -> Result<(), InvokeError> {
-    let res = api_auth_interface::delete_account(email, password).await;
+    let res = api_auth_interface::delete_account(&auth_endpoint(&state).await, email, password).await;
     if let Err(e) = res {
-        return Err(InvokeError::from(format!("Error -> {}", e)));
+        return Err(InvokeError::from(e));
     }
 
     Ok(())
@@ -62,15 +55,17 @@ pub async fn delete_account(email: &str, password: &str, state: State<'_, Arc<Tk
 
 #[tauri::command]
 pub async fn list_nodes(state: State<'_, Arc<TkMutex<GlobalState>>>) -> Result<Vec<HashMap<String, String>>, InvokeError> {
-    let mut nodes = {
+    let (mut nodes, clients) = {
         let lock = state.lock().await;
-        lock.nodes.iter().map(|n| n.to_hashmap()).collect::<Vec<_>>()
+        let nodes = lock.nodes.iter().map(|n| n.to_hashmap()).collect::<Vec<_>>();
+        (nodes, lock.agent_clients())
     };
 
-    let res = {
-        let lock = state.lock().await;
-        agent_interface::check_node_states(&lock).await
-    };
+    // Don't hold the lock while pinging, a down node would block every other command until it times out
+    let res = agent_interface::check_node_states(&clients).await;
+    if let Err(e) = state.lock().await.pin_observed_certs(&clients) {
+        #[cfg(debug_assertions)] println!("Error pinning certificates [fn list_nodes]: {}", e);
+    }
 
     for n in nodes.iter_mut() {
         let id = n.get("node_id").unwrap();
@@ -94,14 +89,14 @@ pub async fn delete_node(node_id: &str, state: State<'_, Arc<TkMutex<GlobalState
         None => return Err(InvokeError::from("No node with that id.")),
     };  
 
-    let (email, pass_sha256) = {
+    let (auth_endpoint, email, pass_sha256) = {
         let lock = state.lock().await;
-        (lock.email.clone() ,crypt_utils::hash_utils::sha256(&lock.password))
+        (lock.auth_endpoint.clone(), lock.email.clone(), lock.pass_sha256.clone())
     };
 
-    let res = api_auth_interface::remove_node(&email, &pass_sha256, node_id).await;
+    let res = api_auth_interface::remove_node(&auth_endpoint, &email, &pass_sha256, node_id).await;
     if let Err(e) = res {
-        return Err(InvokeError::from(format!("Error calling API -> {}", e)));
+        return Err(InvokeError::from(e));
     }
 
     Ok(())
@@ -109,38 +104,275 @@ pub async fn delete_node(node_id: &str, state: State<'_, Arc<TkMutex<GlobalState
 
 #[tauri::command]
 pub async fn get_diags(node_id: &str, state: State<'_, Arc<TkMutex<GlobalState>>>) -> Result<NodeDiags, InvokeError> {
-    let (node, tyb_key) = query_node(node_id, &state).await;
-
-    let node = match node {
+    let (node, client) = match query_client(node_id, &state).await {
         Some(n) => n,
         None => return Err(InvokeError::from("node_id does not exist")),
     };
 
-    let f = agent_interface::get_diags(&node.addr, &tyb_key); // makes an API call
-    let diags = f.await.unwrap_or(NodeDiags::new(node_id, &node.name));
+    let diags = match client {
+        Ok(client) => {
+            let diags = client.get_diags().await.unwrap_or(NodeDiags::new(node_id, &node.name)); // makes an API call
+            pin_observed_cert(client, &state).await;
+            diags
+        },
+        Err(_) => NodeDiags::new(node_id, &node.name),
+    };
     Ok(diags)
 }
 
 #[tauri::command]
 pub async fn get_container_stats(node_id: &str, state: State<'_, Arc<TkMutex<GlobalState>>>) -> Result<Vec<HashMap<String, String>>, InvokeError> {
-    let (node, tyb_key) = query_node(node_id, &state).await;
-
-    if let Some(node) = node {
-        let res = agent_interface::list_container_stats_all(node.addr.clone(), tyb_key.clone()).await;
+    if let Some((_, client)) = query_client(node_id, &state).await {
+        let res = match client {
+            Ok(client) => {
+                let res = client.list_container_stats_all().await;
+                pin_observed_cert(client, &state).await;
+                res
+            },
+            Err(e) => Err(e),
+        };
         if let Ok(res) = res {
             #[cfg(debug_assertions)] println!("Successfully called `get_container_stats`. result -> {:#?}", res);
             return Ok(res);
         }
         else if let Err(e) = res {
-            #[cfg(debug_assertions)] println!("function agent_interface::list_container_stats_all return error [fn get_container_stats]: {}", e);
+            #[cfg(debug_assertions)] println!("function AgentClient::list_container_stats_all return error [fn get_container_stats]: {}", e);
+            return Err(InvokeError::from(e));
         }
     }
     Err(InvokeError::from("No node with that node id"))
 }
 
+#[tauri::command]
+pub async fn stop_container(node_id: &str, proj_name: &str, state: State<'_, Arc<TkMutex<GlobalState>>>) -> Result<(), InvokeError> {
+    container_action(node_id, proj_name, ContainerAction::Stop, &state).await
+}
+
+#[tauri::command]
+pub async fn start_container(node_id: &str, proj_name: &str, state: State<'_, Arc<TkMutex<GlobalState>>>) -> Result<(), InvokeError> {
+    container_action(node_id, proj_name, ContainerAction::Start, &state).await
+}
+
+#[tauri::command]
+pub async fn restart_container(node_id: &str, proj_name: &str, state: State<'_, Arc<TkMutex<GlobalState>>>) -> Result<(), InvokeError> {
+    container_action(node_id, proj_name, ContainerAction::Restart, &state).await
+}
+
+#[tauri::command]
+pub async fn remove_container(node_id: &str, proj_name: &str, state: State<'_, Arc<TkMutex<GlobalState>>>) -> Result<(), InvokeError> {
+    container_action(node_id, proj_name, ContainerAction::Remove, &state).await
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct BuildLogEvent {
+    node_id: String,
+    line: String,
+}
+
+/// Builds a project's image on a node, emitting each line of build output as a `build-log` event.
+#[tauri::command]
+pub async fn build_image(node_id: &str, proj_name: &str, window: Window, state: State<'_, Arc<TkMutex<GlobalState>>>) -> Result<(), InvokeError> {
+    let client = match query_client(node_id, &state).await {
+        Some((_, Ok(client))) => client,
+        Some((_, Err(e))) => return Err(InvokeError::from(e)),
+        None => return Err(InvokeError::from("No node with that node id")),
+    };
+
+    let version = agent_interface::new_release_version();
+    let res = client.build_img_streaming(proj_name, &version, |line| {
+        let event = BuildLogEvent {
+            node_id: node_id.to_string(),
+            line: line.to_string(),
+        };
+        if let Err(e) = window.emit("build-log", event) {
+            #[cfg(debug_assertions)] println!("Error emitting build log [fn build_image]: {}", e);
+        }
+    }).await;
+    pin_observed_cert(client, &state).await;
+
+    if let Err(e) = res {
+        return Err(InvokeError::from(e));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn list_profiles(state: State<'_, Arc<TkMutex<GlobalState>>>) -> Result<Vec<HashMap<String, String>>, InvokeError> {
+    let active = state.lock().await.profile.clone();
+
+    let profiles = global_state::list_profiles()
+        .into_iter()
+        .map(|p| {
+            let mut map = HashMap::new();
+            map.insert("active".to_string(), (p == active).to_string());
+            map.insert("name".to_string(), p);
+            map
+        })
+        .collect();
+
+    Ok(profiles)
+}
+
+/// Swaps the managed state for the given profile so the GUI doesn't need to be restarted.
+#[tauri::command]
+pub async fn switch_profile(profile: &str, state: State<'_, Arc<TkMutex<GlobalState>>>) -> Result<(), InvokeError> {
+    if let Err(e) = global_state::validate_profile_name(profile) {
+        return Err(InvokeError::from(format!("Error -> {}", e)));
+    }
+    if !GlobalState::exists(profile) {
+        return Err(InvokeError::from(format!("No profile named `{}`", profile)));
+    }
+
+    let mut gstate = match GlobalState::load(profile) {
+        Ok(g) => g,
+        Err(e) => return Err(InvokeError::from(format!("Error -> {}", e))),
+    };
+    if let Err(e) = gstate.fetch_nodes().await {
+        return Err(InvokeError::from(format!("Error fetching nodes -> {}", e)));
+    }
+    if let Err(e) = global_state::set_active_profile(profile) {
+        return Err(InvokeError::from(format!("Error -> {}", e)));
+    }
+
+    let mut lock = state.lock().await;
+    *lock = gstate;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn list_history_projects() -> Result<Vec<String>, InvokeError> {
+    Ok(history::list_projects())
+}
+
+/// Past deployments of a project, newest first.
+#[tauri::command]
+pub async fn get_history(proj_name: &str) -> Result<Vec<DeployRecord>, InvokeError> {
+    history::load(proj_name)
+        .map_err(|e| InvokeError::from(format!("Error -> {}", e)))
+}
+
+/// Log streams opened by the GUI, keyed by `<node_id>/<proj_name>`.
+#[derive(Default)]
+pub struct LogStreams(TkMutex<HashMap<String, AbortHandle>>);
+
+#[derive(Debug, Clone, Serialize)]
+struct ContainerLogEvent {
+    node_id: String,
+    proj_name: String,
+    line: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ContainerLogEndEvent {
+    node_id: String,
+    proj_name: String,
+    error: Option<error::TynkerError>,
+}
+
+/// Follows a container's logs in the background, emitting each line as a `container-log` event
+/// and a `container-log-end` event once the stream closes. Replaces any stream already open 
+/// for the same container.
+#[tauri::command]
+pub async fn start_log_stream(
+    node_id: &str, 
+    proj_name: &str, 
+    tail: Option<u32>, 
+    window: Window, 
+    streams: State<'_, LogStreams>, 
+    state: State<'_, Arc<TkMutex<GlobalState>>>,
+) -> Result<(), InvokeError> {
+    let client = match query_client(node_id, &state).await {
+        Some((_, Ok(client))) => client,
+        Some((_, Err(e))) => return Err(InvokeError::from(e)),
+        None => return Err(InvokeError::from("No node with that node id")),
+    };
+
+    let opts = LogOptions {
+        follow: true,
+        since: None,
+        tail: Some(tail.unwrap_or(200)),
+    };
+    let node_id = node_id.to_string();
+    let proj_name = proj_name.to_string();
+    let gstate = state.inner().clone();
+    let key = format!("{}/{}", node_id, proj_name);
+
+    let task = tokio::spawn(async move {
+        let res = client.stream_logs(&proj_name, &opts, |line| {
+            let event = ContainerLogEvent {
+                node_id: node_id.clone(),
+                proj_name: proj_name.clone(),
+                line: line.to_string(),
+            };
+            if let Err(e) = window.emit("container-log", event) {
+                #[cfg(debug_assertions)] println!("Error emitting container log [fn start_log_stream]: {}", e);
+            }
+        }).await;
+
+        let event = ContainerLogEndEvent {
+            node_id: node_id.clone(),
+            proj_name: proj_name.clone(),
+            error: res.err(),
+        };
+        window.emit("container-log-end", event).ok();
+
+        if let Err(e) = gstate.lock().await.pin_observed_certs(&[client]) {
+            #[cfg(debug_assertions)] println!("Error pinning certificate [fn start_log_stream]: {}", e);
+        }
+    });
+
+    if let Some(previous) = streams.0.lock().await.insert(key, task.abort_handle()) {
+        previous.abort();
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn stop_log_stream(node_id: &str, proj_name: &str, streams: State<'_, LogStreams>) -> Result<(), InvokeError> {
+    if let Some(handle) = streams.0.lock().await.remove(&format!("{}/{}", node_id, proj_name)) {
+        handle.abort();
+    }
+    Ok(())
+}
+
+async fn container_action(node_id: &str, proj_name: &str, action: ContainerAction, state: &State<'_, Arc<TkMutex<GlobalState>>>) -> Result<(), InvokeError> {
+    let client = match query_client(node_id, state).await {
+        Some((_, Ok(client))) => client,
+        Some((_, Err(e))) => return Err(InvokeError::from(e)),
+        None => return Err(InvokeError::from("No node with that node id")),
+    };
+
+    let res = client.container_action(proj_name, action).await;
+    pin_observed_cert(client, state).await;
+
+    if let Err(e) = res {
+        return Err(InvokeError::from(e));
+    }
+    Ok(())
+}
+
+async fn auth_endpoint(state: &State<'_, Arc<TkMutex<GlobalState>>>) -> String {
+    state.lock().await.auth_endpoint.clone()
+}
+
+/// Looks up a node along with a client that trusts the node's pinned certificate.
+async fn query_client(node_id: &str, state: &State<'_, Arc<TkMutex<GlobalState>>>) -> Option<(Node, error::Result<AgentClient>)> {
+    let lock = state.lock().await;
+    let node = lock.nodes.iter().find(|n| n.node_id == node_id)?.clone();
+    let client = lock.agent_client(&node);
+    Some((node, client))
+}
+
+async fn pin_observed_cert(client: AgentClient, state: &State<'_, Arc<TkMutex<GlobalState>>>) {
+    let mut lock = state.lock().await;
+    if let Err(e) = lock.pin_observed_certs(&[client]) {
+        #[cfg(debug_assertions)] println!("Error pinning certificate [fn pin_observed_cert]: {}", e);
+    }
+}
+
 async fn query_node(node_id: &str, state: &State<'_, Arc<TkMutex<GlobalState>>>) -> (Option<Node>, String) {
     let lock = state.lock().await;
-    let tyb_key = lock.tyb_key.clone();
+    let tyb_key = lock.api_key().to_string();
     for n in lock.nodes.iter() {
         if n.node_id == node_id {
             return (Some(n.clone()), tyb_key);
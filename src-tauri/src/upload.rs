@@ -0,0 +1,265 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+use tynkerbase_universal::file_utils::FileCollection;
+
+use crate::error::{Result, TynkerError};
+
+/// Size of each chunk of an upload. Chunks are sent (and retried) independently.
+pub const UPLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Compression applied to a project upload before it's split into chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Compression {
+    None,
+    #[default]
+    Brotli,
+    Zstd,
+}
+
+impl Compression {
+    /// Name of the compression as expected by the agent's upload routes.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Brotli => "brotli",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+/// Uploads are identified by the files they contain and how they're compressed, so an
+/// interrupted upload of the same files can pick up where it left off.
+pub fn upload_id(files: &FileCollection, compression: Compression) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(compression.as_str().as_bytes());
+    for (path, contents) in files.files.iter().zip(files.contents.iter()) {
+        hasher.update(b"\n");
+        hasher.update(path.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(Sha256::digest(contents));
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Estimated compressed size of an upload of `size` bytes, given that the first `read` bytes
+/// produced the `sent` bytes uploaded so far. Falls back to `size` until there's a ratio to go by.
+pub fn estimated_size(sent: u64, read: u64, size: u64) -> u64 {
+    if sent == 0 || read == 0 {
+        return size;
+    }
+    (sent as u128 * size as u128 / read as u128) as u64
+}
+
+/// Compressed output waiting to be cut into chunks. Shared with the encoder, which owns its writer.
+#[derive(Clone, Default)]
+struct Sink(Arc<Mutex<Vec<u8>>>);
+
+impl Sink {
+    /// Takes the full chunks out of the buffer, and what's left over if `last`.
+    fn take_chunks(&self, last: bool) -> Vec<Vec<u8>> {
+        let mut buf = self.0.lock().unwrap();
+        let mut chunks = vec![];
+        while buf.len() >= UPLOAD_CHUNK_SIZE {
+            let rest = buf.split_off(UPLOAD_CHUNK_SIZE);
+            chunks.push(std::mem::replace(&mut *buf, rest));
+        }
+        if last && buf.len() > 0 {
+            chunks.push(std::mem::take(&mut *buf));
+        }
+        chunks
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Encoder {
+    None(Sink),
+    Brotli(brotli::CompressorWriter<Sink>),
+    Zstd(zstd::Encoder<'static, Sink>),
+}
+
+impl Encoder {
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Encoder::None(w) => w,
+            Encoder::Brotli(w) => w,
+            Encoder::Zstd(w) => w,
+        }
+    }
+}
+
+/// Compresses a project one file at a time and cuts the output into chunks of
+/// `UPLOAD_CHUNK_SIZE`, so only the chunk being filled is held in memory.
+///
+/// Before compression, the payload is every file as a bincode encoded `(path, contents)`
+/// pair, one after the other. The same files always produce the same chunks.
+pub struct ChunkWriter {
+    encoder: Encoder,
+    sink: Sink,
+}
+
+impl ChunkWriter {
+    pub fn new(compression: Compression) -> Result<Self> {
+        let sink = Sink::default();
+        let encoder = match compression {
+            Compression::None => Encoder::None(sink.clone()),
+            Compression::Brotli => Encoder::Brotli(brotli::CompressorWriter::new(sink.clone(), 4096, 9, 22)),
+            Compression::Zstd => Encoder::Zstd(
+                zstd::Encoder::new(sink.clone(), 3)
+                    .map_err(|e| TynkerError::Other(format!("Error starting zstd compression -> {}", e)))?
+            ),
+        };
+        Ok(ChunkWriter { encoder, sink })
+    }
+
+    /// Adds a file to the payload, returning the chunks it completed.
+    pub fn add_file(&mut self, path: &str, contents: &[u8]) -> Result<Vec<Vec<u8>>> {
+        bincode::serialize_into(self.encoder.writer(), &(path, contents))
+            .map_err(|e| TynkerError::Other(format!("Error compressing `{}` -> {}", path, e)))?;
+        Ok(self.sink.take_chunks(false))
+    }
+
+    /// Ends the payload, returning the remaining chunks. The last one may be shorter.
+    pub fn finish(self) -> Result<Vec<Vec<u8>>> {
+        match self.encoder {
+            Encoder::None(_) => {},
+            // Writes the end of the brotli stream
+            Encoder::Brotli(w) => {
+                w.into_inner();
+            },
+            Encoder::Zstd(w) => {
+                w.finish()
+                    .map_err(|e| TynkerError::Other(format!("Error compressing upload with zstd -> {}", e)))?;
+            },
+        }
+        Ok(self.sink.take_chunks(true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    fn files(files: &[(&str, Vec<u8>)]) -> FileCollection {
+        FileCollection {
+            files: files.iter().map(|(p, _)| p.to_string()).collect(),
+            contents: files.iter().map(|(_, c)| c.clone()).collect(),
+        }
+    }
+
+    /// Incompressible bytes, so the compressed upload spans several chunks.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state: u32 = 0x9e3779b9;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn chunks(files: &FileCollection, compression: Compression) -> Vec<Vec<u8>> {
+        let mut writer = ChunkWriter::new(compression).unwrap();
+        let mut chunks = vec![];
+        for (path, contents) in files.files.iter().zip(files.contents.iter()) {
+            chunks.extend(writer.add_file(path, contents).unwrap());
+        }
+        chunks.extend(writer.finish().unwrap());
+        chunks
+    }
+
+    fn decompress(payload: &[u8], compression: Compression) -> Vec<u8> {
+        let mut out = vec![];
+        match compression {
+            Compression::None => out.extend_from_slice(payload),
+            Compression::Brotli => {
+                brotli::Decompressor::new(payload, 4096).read_to_end(&mut out).unwrap();
+            }
+            Compression::Zstd => out = zstd::decode_all(payload).unwrap(),
+        }
+        out
+    }
+
+    fn unpack(payload: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut cursor = Cursor::new(payload);
+        let mut files = vec![];
+        while (cursor.position() as usize) < payload.len() {
+            files.push(bincode::deserialize_from(&mut cursor).unwrap());
+        }
+        files
+    }
+
+    fn round_trip(compression: Compression) {
+        let project = files(&[
+            ("Dockerfile", b"FROM python:3.12\n".to_vec()),
+            ("empty.txt", vec![]),
+            ("data.bin", noise(UPLOAD_CHUNK_SIZE * 2 + 100)),
+        ]);
+
+        let chunks = chunks(&project, compression);
+        assert!(chunks.len() >= 3, "{} chunks", chunks.len());
+        let payload = decompress(&chunks.concat(), compression);
+
+        let expected = project.files.iter().cloned().zip(project.contents.iter().cloned()).collect::<Vec<_>>();
+        assert_eq!(unpack(&payload), expected);
+    }
+
+    #[test]
+    fn brotli_uploads_round_trip() {
+        round_trip(Compression::Brotli);
+    }
+
+    #[test]
+    fn zstd_uploads_round_trip() {
+        round_trip(Compression::Zstd);
+    }
+
+    #[test]
+    fn output_is_split_on_chunk_boundaries() {
+        let project = files(&[("a", noise(UPLOAD_CHUNK_SIZE - 10)), ("b", noise(UPLOAD_CHUNK_SIZE + 10))]);
+
+        let chunks = chunks(&project, Compression::None);
+        let (last, full) = chunks.split_last().unwrap();
+        assert_eq!(full.len(), 2);
+        assert!(full.iter().all(|c| c.len() == UPLOAD_CHUNK_SIZE));
+        assert!(last.len() > 0 && last.len() <= UPLOAD_CHUNK_SIZE);
+        assert_eq!(unpack(&chunks.concat()).len(), 2);
+    }
+
+    #[test]
+    fn same_files_resume_the_same_upload() {
+        // Chunks the node already has are only skipped if a retry produces the same ones
+        let project = files(&[("a", noise(UPLOAD_CHUNK_SIZE * 2)), ("b", b"b".to_vec())]);
+        for compression in [Compression::None, Compression::Brotli, Compression::Zstd] {
+            assert_eq!(upload_id(&project, compression), upload_id(&project, compression));
+            assert_eq!(chunks(&project, compression), chunks(&project, compression));
+        }
+
+        let changed = files(&[("a", noise(UPLOAD_CHUNK_SIZE * 2)), ("b", b"c".to_vec())]);
+        assert_ne!(upload_id(&project, Compression::Brotli), upload_id(&changed, Compression::Brotli));
+        assert_ne!(upload_id(&project, Compression::Brotli), upload_id(&project, Compression::Zstd));
+    }
+
+    #[test]
+    fn estimated_size_follows_the_compression_ratio() {
+        assert_eq!(estimated_size(0, 0, 1000), 1000);
+        assert_eq!(estimated_size(0, 500, 1000), 1000);
+        assert_eq!(estimated_size(100, 500, 1000), 200);
+        assert_eq!(estimated_size(300, 1000, 1000), 300);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::consts;
+use serde::{Serialize, Deserialize};
+use anyhow::{anyhow, Result};
+use rand::RngCore;
+use ring::aead::{
+    Aad,
+    LessSafeKey,
+    Nonce,
+    UnboundKey,
+    AES_256_GCM,
+    NONCE_LEN,
+};
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+};
+
+const KEY_LEN: usize = 32;
+
+/// The secret half of the global state. This is only ever written to disk
+/// inside of a `SealedBox`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Credentials {
+    pub pass_sha256: String,
+    pub pass_sha384: String,
+    pub tyb_key: String,
+}
+
+/// AES-256-GCM encrypted payload along with the nonce it was sealed with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedBox {
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+}
+
+pub fn key_path() -> String {
+    format!("{}/vault.key", consts::app_data())
+}
+
+/// Loads the local vault key, generating one (readable only by the current user)
+/// if it doesn't exist yet.
+fn load_or_create_key() -> Result<[u8; KEY_LEN]> {
+    let path = key_path();
+
+    if Path::new(&path).exists() {
+        let bytes = fs::read(&path)
+            .map_err(|e| anyhow!("Error reading vault key `{}` -> {}", &path, e))?;
+        let key: [u8; KEY_LEN] = bytes
+            .try_into()
+            .map_err(|_| anyhow!("Vault key `{}` is corrupted", &path))?;
+        return Ok(key);
+    }
+
+    let app_data = consts::app_data();
+    if !Path::new(&app_data).exists() {
+        fs::create_dir_all(&app_data)
+            .map_err(|e| anyhow!("Error creating app data directory -> {}", e))?;
+    }
+
+    let mut key = [0u8; KEY_LEN];
+    rand::thread_rng().fill_bytes(&mut key);
+
+    let mut opts = fs::OpenOptions::new();
+    opts.write(true).create_new(true);
+    #[cfg(unix)] {
+        use std::os::unix::fs::OpenOptionsExt;
+        opts.mode(0o600);
+    }
+    let mut file = opts.open(&path)
+        .map_err(|e| anyhow!("Error creating vault key `{}` -> {}", &path, e))?;
+    file.write_all(&key)
+        .map_err(|e| anyhow!("Error writing vault key -> {}", e))?;
+
+    Ok(key)
+}
+
+fn cipher() -> Result<LessSafeKey> {
+    let key = load_or_create_key()?;
+    let key = UnboundKey::new(&AES_256_GCM, &key)
+        .map_err(|_| anyhow!("Failed to initialize vault cipher"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+pub fn seal(creds: &Credentials) -> Result<SealedBox> {
+    let plaintext = bincode::serialize(creds)
+        .map_err(|e| anyhow!("Error serializing credentials -> {}", e))?;
+    seal_bytes(plaintext)
+}
+
+pub fn open(sealed: &SealedBox) -> Result<Credentials> {
+    let plaintext = open_bytes(sealed)
+        .map_err(|e| anyhow!("{}. Log in again.", e))?;
+
+    bincode::deserialize(&plaintext)
+        .map_err(|e| anyhow!("Failed to deserialize credentials -> {}", e))
+}
+
+pub fn seal_bytes(mut plaintext: Vec<u8>) -> Result<SealedBox> {
+    let cipher = cipher()?;
+
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    cipher
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut plaintext)
+        .map_err(|_| anyhow!("Failed to encrypt data"))?;
+
+    Ok(SealedBox { nonce, ciphertext: plaintext })
+}
+
+pub fn open_bytes(sealed: &SealedBox) -> Result<Vec<u8>> {
+    let cipher = cipher()?;
+
+    let mut buf = sealed.ciphertext.clone();
+    let len = cipher
+        .open_in_place(Nonce::assume_unique_for_key(sealed.nonce), Aad::empty(), &mut buf)
+        .map_err(|_| anyhow!("Failed to decrypt data, the vault key at `{}` may have changed", key_path()))?
+        .len();
+    buf.truncate(len);
+    Ok(buf)
+}
@@ -0,0 +1,440 @@
+//! Stand-in for the auth server and a node's agent that runs on localhost, so the clients can
+//! be tested without a live node or the hosted server. Both sets of routes are served from the
+//! same address, over plain http.
+//!
+//! Uploads are unpacked into each project's files, which the manifest is computed from, so
+//! incremental syncs behave like they do against a real agent.
+
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::{HeaderMap, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Router,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Cursor, Read},
+    sync::{Arc, Mutex, MutexGuard},
+};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpListener;
+use tynkerbase_universal::{
+    constants::TYB_APIKEY_HTTP_HEADER,
+    netwk_utils::{Node, ProjConfig},
+};
+
+use tynkerbase_client::agent_interface::{Release, Resources, SpawnOptions};
+use tynkerbase_client::BUILD_STATUS_PREFIX;
+
+/// Salt the mock auth server hands out on login, api keys are derived from it.
+pub const SALT: &str = "mock-salt";
+
+#[derive(Debug, Clone, Default)]
+pub struct MockProject {
+    /// Contents of the project's files, keyed by path.
+    pub files: BTreeMap<String, Vec<u8>>,
+    /// Sorted paths in every completed upload, oldest first.
+    pub uploads: Vec<Vec<String>>,
+    pub releases: Vec<Release>,
+    pub running: bool,
+    pub env: BTreeMap<String, String>,
+    pub resources: Resources,
+}
+
+#[derive(Debug, Default)]
+pub struct MockState {
+    /// Sha256 of each account's password, keyed by email.
+    pub accounts: HashMap<String, String>,
+    pub nodes: Vec<Node>,
+    /// Status and body of the response to a login with the wrong password.
+    pub wrong_password: (StatusCode, String),
+    /// Key the agent routes accept.
+    pub api_key: String,
+    /// Optional agent features advertised on `/capabilities`.
+    pub capabilities: Vec<String>,
+    pub projects: BTreeMap<String, MockProject>,
+    /// Chunks of uploads that haven't been finished, keyed by upload id.
+    pub chunks: HashMap<String, BTreeMap<u64, Vec<u8>>>,
+    /// Make every build fail.
+    pub fail_builds: bool,
+    /// Reject upload chunks with this index.
+    pub fail_chunk: Option<u64>,
+    /// Index of every upload chunk stored, in order.
+    pub chunks_stored: Vec<u64>,
+    /// Method and path of every request received, in order.
+    pub requests: Vec<String>,
+}
+
+type Shared = Arc<Mutex<MockState>>;
+
+pub struct MockServer {
+    addr: String,
+    state: Shared,
+}
+
+impl MockServer {
+    /// Starts the server on a free port. It runs until the runtime it was started on shuts down.
+    pub async fn start(api_key: &str) -> Self {
+        let state = Arc::new(Mutex::new(MockState {
+            api_key: api_key.to_string(),
+            wrong_password: (StatusCode::OK, "Incorrect password".to_string()),
+            capabilities: vec!["chunked-upload".to_string(), "manifest".to_string()],
+            ..Default::default()
+        }));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+
+        let app = Router::new()
+            .fallback(handle)
+            .with_state(state.clone());
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        MockServer { addr, state }
+    }
+
+    /// Base url of the server, usable both as the auth endpoint and as a node's address.
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Registers a node served by this mock under the account `email`.
+    pub fn add_node(&self, email: &str, name: &str) -> Node {
+        let node = Node {
+            node_id: format!("mock-node-{}", name),
+            name: name.to_string(),
+            email: email.to_string(),
+            addr: self.addr.clone(),
+        };
+        self.state().nodes.push(node.clone());
+        node
+    }
+}
+
+async fn handle(
+    State(state): State<Shared>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    Query(query): Query<HashMap<String, String>>,
+    body: Bytes,
+) -> Response {
+    let mut state = state.lock().unwrap();
+    let path = uri.path().to_string();
+    state.requests.push(format!("{} {}", method, path));
+
+    if path.starts_with("/auth/") || path.starts_with("/ngrok/") {
+        return auth(&mut state, &path, &query);
+    }
+
+    // The agent answers pings with its id without checking the key
+    if path == "/" && !headers.contains_key(TYB_APIKEY_HTTP_HEADER) {
+        return (StatusCode::OK, "mock-node").into_response();
+    }
+    let key = headers
+        .get(TYB_APIKEY_HTTP_HEADER)
+        .and_then(|k| k.to_str().ok())
+        .unwrap_or_default();
+    if key != state.api_key {
+        return (StatusCode::UNAUTHORIZED, "invalid api key").into_response();
+    }
+    agent(&mut state, &path, &query, &body)
+}
+
+fn auth(state: &mut MockState, path: &str, query: &HashMap<String, String>) -> Response {
+    let param = |k: &str| query.get(k).cloned().unwrap_or_default();
+    let (email, pass_sha256) = (param("email"), param("pass_sha256"));
+    let authorized = state.accounts.get(&email) == Some(&pass_sha256);
+
+    match path {
+        "/auth/create-account" => {
+            if state.accounts.contains_key(&email) {
+                return (StatusCode::CONFLICT, "account already exists").into_response();
+            }
+            state.accounts.insert(email, pass_sha256);
+            StatusCode::OK.into_response()
+        }
+        "/auth/delete-account" if authorized => {
+            state.accounts.remove(&email);
+            state.nodes.retain(|n| n.email != email);
+            StatusCode::OK.into_response()
+        }
+        // Like the real server, bad credentials still get a 200 unless a test changes it
+        "/auth/login" if authorized => (StatusCode::OK, SALT).into_response(),
+        "/auth/login" => state.wrong_password.clone().into_response(),
+        "/ngrok/get-all-addrs" if authorized => {
+            let nodes = state.nodes
+                .iter()
+                .filter(|n| n.email == email)
+                .cloned()
+                .collect::<Vec<Node>>();
+            binary(&nodes)
+        }
+        "/ngrok/remove-addr" if authorized => {
+            let node_id = param("node_id");
+            state.nodes.retain(|n| n.node_id != node_id);
+            StatusCode::OK.into_response()
+        }
+        "/auth/delete-account" | "/ngrok/get-all-addrs" | "/ngrok/remove-addr" => {
+            (StatusCode::UNAUTHORIZED, "incorrect email or password").into_response()
+        }
+        _ => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+fn agent(state: &mut MockState, path: &str, query: &HashMap<String, String>, body: &[u8]) -> Response {
+    let param = |k: &str| query.get(k).cloned().unwrap_or_default();
+    let name = param("name");
+    let not_found = || (StatusCode::NOT_FOUND, format!("project `{}` does not exist", name)).into_response();
+
+    match path {
+        "/" => (StatusCode::OK, "mock-node").into_response(),
+        "/capabilities" => binary(&state.capabilities),
+        "/diags/get-diags" => {
+            let diags = serde_json::json!({
+                "node_id": "mock-node",
+                "name": "mock",
+                "cpu": "Mock CPU",
+                "hardware_threads": "4",
+                "mem_total": "8 GB",
+                "mem_free": "4 GB",
+            });
+            (StatusCode::OK, diags.to_string()).into_response()
+        }
+        "/files/proj/create-proj" => {
+            state.projects.entry(name.clone()).or_default();
+            StatusCode::OK.into_response()
+        }
+        "/files/proj/delete-proj" => match state.projects.remove(&name) {
+            Some(_) => StatusCode::OK.into_response(),
+            None => not_found(),
+        },
+        // Like the agent, purging removes the project's files but keeps its release images
+        "/files/proj/purge-project" => {
+            if let Some(p) = state.projects.get_mut(&name) {
+                p.files.clear();
+            }
+            StatusCode::OK.into_response()
+        }
+        "/files/proj/list-projects" => binary(&state.projects.keys().cloned().collect::<Vec<String>>()),
+        "/files/proj/get-manifest" => match state.projects.get(&name) {
+            Some(p) => {
+                let manifest = p.files
+                    .iter()
+                    .map(|(path, contents)| (path.clone(), hex::encode(Sha256::digest(contents))))
+                    .collect::<HashMap<String, String>>();
+                binary(&manifest)
+            }
+            None => not_found(),
+        },
+        "/files/proj/delete-files" => {
+            let Some(p) = state.projects.get_mut(&name) else {
+                return not_found();
+            };
+            let Ok(paths) = bincode::deserialize::<Vec<String>>(body) else {
+                return (StatusCode::BAD_REQUEST, "invalid paths").into_response();
+            };
+            for path in paths {
+                p.files.remove(&path);
+            }
+            StatusCode::OK.into_response()
+        }
+        "/files/proj/upload/status" => {
+            if !state.projects.contains_key(&name) {
+                return not_found();
+            }
+            let received = state.chunks
+                .get(&param("upload_id"))
+                .map(|c| c.keys().copied().collect::<Vec<u64>>())
+                .unwrap_or_default();
+            binary(&received)
+        }
+        "/files/proj/upload/chunk" => {
+            let Ok(index) = param("index").parse::<u64>() else {
+                return (StatusCode::BAD_REQUEST, "invalid chunk index").into_response();
+            };
+            if state.fail_chunk == Some(index) {
+                return (StatusCode::INTERNAL_SERVER_ERROR, "mock chunk failure").into_response();
+            }
+            state.chunks_stored.push(index);
+            state.chunks
+                .entry(param("upload_id"))
+                .or_default()
+                .insert(index, body.to_vec());
+            StatusCode::OK.into_response()
+        }
+        "/files/proj/upload/finish" => {
+            if !state.projects.contains_key(&name) {
+                return not_found();
+            }
+            let chunks = state.chunks.remove(&param("upload_id")).unwrap_or_default();
+            if chunks.len().to_string() != param("chunks") {
+                return (StatusCode::BAD_REQUEST, "upload is missing chunks").into_response();
+            }
+            let payload = chunks.into_values().flatten().collect::<Vec<u8>>();
+            let files = match decompress(&payload, &param("compression")).and_then(|p| unpack(&p)) {
+                Ok(f) => f,
+                Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+            };
+            let p = state.projects.get_mut(&name).unwrap();
+            let mut paths = files.iter().map(|(path, _)| path.clone()).collect::<Vec<String>>();
+            paths.sort();
+            p.uploads.push(paths);
+            p.files.extend(files);
+            StatusCode::OK.into_response()
+        }
+        "/docker/proj/build-img" | "/docker/proj/build-img-stream" => {
+            let fail_builds = state.fail_builds;
+            let Some(p) = state.projects.get_mut(&name) else {
+                return not_found();
+            };
+            let streaming = path.ends_with("-stream");
+            if p.files.len() == 0 || fail_builds {
+                let reason = if fail_builds { "mock build failure" } else { "no files uploaded" };
+                if streaming {
+                    let log = format!("Step 1/1 : RUN build\n{}\n{} failed\n", reason, BUILD_STATUS_PREFIX);
+                    return (StatusCode::OK, log).into_response();
+                }
+                return (StatusCode::INTERNAL_SERVER_ERROR, reason.to_string()).into_response();
+            }
+
+            let version = param("version");
+            p.releases.retain(|r| r.version != version);
+            p.releases.push(Release {
+                version,
+                created_at: chrono::Utc::now().to_rfc3339(),
+                active: false,
+            });
+            let log = if streaming {
+                format!("Step 1/1 : RUN build\nSuccessfully built\n{} ok\n", BUILD_STATUS_PREFIX)
+            }
+            else {
+                String::new()
+            };
+            (StatusCode::OK, log).into_response()
+        }
+        "/docker/proj/spawn-container" | "/docker/proj/spawn-container-with-options" => {
+            let parsed = if path.ends_with("-with-options") {
+                bincode::deserialize::<(ProjConfig, SpawnOptions)>(body)
+            }
+            else {
+                bincode::deserialize::<ProjConfig>(body).map(|c| (c, SpawnOptions::default()))
+            };
+            let Ok((conf, opts)) = parsed else {
+                return (StatusCode::BAD_REQUEST, "invalid config").into_response();
+            };
+            let Some(p) = state.projects.get_mut(&conf.proj_name) else {
+                return (StatusCode::NOT_FOUND, format!("project `{}` does not exist", conf.proj_name)).into_response();
+            };
+            let version = param("version");
+            if !p.releases.iter().any(|r| r.version == version) {
+                return (StatusCode::BAD_REQUEST, format!("release `{}` hasn't been built", version)).into_response();
+            }
+            for r in p.releases.iter_mut() {
+                r.active = r.version == version;
+            }
+            p.running = true;
+            p.env = opts.env;
+            p.resources = opts.resources;
+            StatusCode::OK.into_response()
+        }
+        "/docker/proj/list-releases" => match state.projects.get(&name) {
+            Some(p) => binary(&p.releases),
+            None => not_found(),
+        },
+        "/docker/proj/rollback" => {
+            let Some(p) = state.projects.get_mut(&name) else {
+                return not_found();
+            };
+            let version = param("version");
+            if !p.releases.iter().any(|r| r.version == version) {
+                return (StatusCode::BAD_REQUEST, format!("no release `{}`", version)).into_response();
+            }
+            for r in p.releases.iter_mut() {
+                r.active = r.version == version;
+            }
+            p.running = true;
+            StatusCode::OK.into_response()
+        }
+        "/docker/proj/stop-container"
+        | "/docker/proj/start-container"
+        | "/docker/proj/restart-container"
+        | "/docker/proj/remove-container" => {
+            let Some(p) = state.projects.get_mut(&name) else {
+                return not_found();
+            };
+            p.running = matches!(path, "/docker/proj/start-container" | "/docker/proj/restart-container");
+            StatusCode::OK.into_response()
+        }
+        "/docker/proj/list-containers" => {
+            let mut table = vec!["CONTAINER ID|||IMAGE|||COMMAND|||CREATED AT|||STATUS|||PORTS|||NAMES".to_string()];
+            for (i, (name, p)) in state.projects.iter().enumerate() {
+                let Some(release) = p.releases.iter().find(|r| r.active) else {
+                    continue;
+                };
+                let status = if p.running { "Up 5 seconds" } else { "Exited (0) 5 seconds ago" };
+                table.push(format!(
+                    "{:012}|||{}:{}|||\"./run\"|||{}|||{}|||0.0.0.0:8080->8080/tcp|||{}",
+                    i, name, release.version, release.created_at, status, name,
+                ));
+            }
+            (StatusCode::OK, table.join("\n")).into_response()
+        }
+        "/docker/proj/list-container-stats" => {
+            let mut table = vec!["CONTAINER ID|||CONTAINER|||CPU %|||MEM USAGE / LIMIT|||MEM %|||NET I/O|||BLOCK I/O|||PIDS".to_string()];
+            for (i, (name, p)) in state.projects.iter().enumerate() {
+                if p.running {
+                    table.push(format!("{:012}|||{}|||0.50%|||10MiB / 8GiB|||0.12%|||1kB / 1kB|||0B / 0B|||2", i, name));
+                }
+            }
+            (StatusCode::OK, table.join("\n")).into_response()
+        }
+        "/docker/proj/logs" => {
+            if !state.projects.contains_key(&name) {
+                return not_found();
+            }
+            let lines = (1..=5).map(|i| format!("log line {}", i)).collect::<Vec<String>>();
+            let tail = param("tail").parse::<usize>().unwrap_or(lines.len()).min(lines.len());
+            (StatusCode::OK, lines[lines.len() - tail..].join("\n")).into_response()
+        }
+        _ => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+fn binary<T: serde::Serialize>(value: &T) -> Response {
+    (StatusCode::OK, bincode::serialize(value).unwrap()).into_response()
+}
+
+fn decompress(payload: &[u8], compression: &str) -> Result<Vec<u8>, String> {
+    match compression {
+        "none" => Ok(payload.to_vec()),
+        "brotli" => {
+            let mut out = vec![];
+            brotli::Decompressor::new(payload, 4096)
+                .read_to_end(&mut out)
+                .map_err(|e| format!("invalid brotli payload: {}", e))?;
+            Ok(out)
+        }
+        "zstd" => zstd::decode_all(payload).map_err(|e| format!("invalid zstd payload: {}", e)),
+        c => Err(format!("unknown compression `{}`", c)),
+    }
+}
+
+/// Splits a decompressed upload into the `(path, contents)` pairs it was written as.
+fn unpack(payload: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut cursor = Cursor::new(payload);
+    let mut files = vec![];
+    while (cursor.position() as usize) < payload.len() {
+        let file = bincode::deserialize_from::<_, (String, Vec<u8>)>(&mut cursor)
+            .map_err(|e| format!("invalid upload payload: {}", e))?;
+        files.push(file);
+    }
+    Ok(files)
+}
@@ -0,0 +1,522 @@
+//! End to end tests of the auth and agent clients against the mock server in `common`.
+
+mod common;
+
+use std::{collections::BTreeMap, fs, sync::Arc};
+
+use axum::http::StatusCode;
+
+use tynkerbase_universal::{
+    crypt_utils::{gen_apikey, hash_utils},
+    file_utils::FileCollection,
+    netwk_utils::{Node, ProjConfig},
+};
+
+use tynkerbase_client::{
+    agent_interface::{self, AgentClient, ContainerAction, LogOptions, Resources, RetryPolicy, SpawnOptions},
+    api_auth_interface,
+    config::{DeployConfig, Strategy},
+    deploy::{Deployment, Reporter, Silent},
+    error::{self, TynkerError},
+    file_sync,
+    global_state::GlobalState,
+    history::StepOutcome,
+    Compression,
+};
+
+use common::{MockServer, SALT};
+
+const EMAIL: &str = "dev@example.com";
+const PASSWORD: &str = "correct horse battery staple";
+
+fn api_key() -> String {
+    gen_apikey(&hash_utils::sha384(PASSWORD), SALT)
+}
+
+/// Mock server with an account for `EMAIL` and a node named `node-1`.
+async fn setup() -> (MockServer, Node) {
+    let mock = MockServer::start(&api_key()).await;
+    api_auth_interface::create_account(mock.addr(), EMAIL, PASSWORD).await.unwrap();
+    let node = mock.add_node(EMAIL, "node-1");
+    (mock, node)
+}
+
+const DOCKERFILE: &str = "FROM python:3.12\nCOPY . .\nCMD [\"python\", \"main.py\"]\n";
+
+/// Writes `files` to a fresh directory and loads them as a project.
+fn load_project(files: &[(&str, &str)]) -> FileCollection {
+    let dir = tempfile::tempdir().unwrap();
+    for (path, contents) in files {
+        fs::write(dir.path().join(path), contents).unwrap();
+    }
+    let path = format!("{}/", dir.path().to_str().unwrap());
+    FileCollection::load(&path, &vec![]).unwrap()
+}
+
+/// A small python project.
+fn project_files() -> FileCollection {
+    load_project(&[("Dockerfile", DOCKERFILE), ("main.py", "print('hello')\n")])
+}
+
+fn proj_config(name: &str) -> ProjConfig {
+    let mut conf = ProjConfig::default();
+    conf.proj_name = name.to_string();
+    conf
+}
+
+/// Runs every step of `tyb deploy` against a single node.
+async fn deploy(client: &AgentClient, name: &str, version: &str, opts: &SpawnOptions) -> error::Result<()> {
+    let files = project_files();
+    let manifest = file_sync::manifest(&files);
+    client.sync_proj(name, &files, &manifest, &mut |_, _| {}).await?;
+    client.build_img_streaming(name, version, |_| {}).await?;
+    client.spawn_container(&proj_config(name), version, opts).await
+}
+
+#[tokio::test]
+async fn login_returns_the_api_key() {
+    let (mock, _) = setup().await;
+
+    let key = api_auth_interface::login(mock.addr(), EMAIL, PASSWORD).await.unwrap();
+    assert_eq!(key, api_key());
+}
+
+#[tokio::test]
+async fn login_with_wrong_password_is_unauthorized() {
+    let (mock, _) = setup().await;
+
+    let res = api_auth_interface::login(mock.addr(), EMAIL, "wrong").await;
+    assert!(matches!(res, Err(TynkerError::Unauthorized(_))), "{:?}", res);
+}
+
+#[tokio::test]
+async fn wrong_password_is_detected_in_any_form() {
+    let (mock, _) = setup().await;
+
+    let responses = [
+        (StatusCode::OK, "Incorrect password\n"),
+        (StatusCode::OK, "\"Incorrect password\""),
+        (StatusCode::UNAUTHORIZED, "bad credentials"),
+    ];
+    for (status, body) in responses {
+        mock.state().wrong_password = (status, body.to_string());
+        let res = api_auth_interface::login(mock.addr(), EMAIL, "wrong").await;
+        assert!(matches!(res, Err(TynkerError::Unauthorized(_))), "{:?} for {:?}", res, body);
+    }
+}
+
+#[tokio::test]
+async fn create_account_twice_fails() {
+    let (mock, _) = setup().await;
+
+    let res = api_auth_interface::create_account(mock.addr(), EMAIL, PASSWORD).await;
+    assert!(matches!(res, Err(TynkerError::Http { status: 409, .. })), "{:?}", res);
+}
+
+#[tokio::test]
+async fn list_nodes_reports_node_states() {
+    let (mock, node) = setup().await;
+    let mut offline = node.clone();
+    offline.node_id = "mock-node-offline".to_string();
+    offline.name = "offline".to_string();
+    // Nothing listens on port 9 of localhost
+    offline.addr = "http://127.0.0.1:9".to_string();
+    mock.state().nodes.push(offline);
+
+    let key = api_auth_interface::login(mock.addr(), EMAIL, PASSWORD).await.unwrap();
+    let mut gstate = GlobalState::new("test", mock.addr(), EMAIL, PASSWORD, &key);
+    gstate.fetch_nodes().await.unwrap();
+
+    let mut names = gstate.nodes.iter().map(|n| n.name.clone()).collect::<Vec<String>>();
+    names.sort();
+    assert_eq!(names, vec!["node-1", "offline"]);
+
+    let states = agent_interface::check_node_states(&mut gstate).await;
+    assert_eq!(states.get(&node.node_id), Some(&true));
+    assert_eq!(states.get("mock-node-offline"), Some(&false));
+}
+
+#[tokio::test]
+async fn removed_nodes_are_no_longer_listed() {
+    let (mock, node) = setup().await;
+
+    api_auth_interface::remove_node(mock.addr(), EMAIL, &hash_utils::sha256(PASSWORD), &node.node_id).await.unwrap();
+    let nodes = api_auth_interface::get_nodes(mock.addr(), EMAIL, &hash_utils::sha256(PASSWORD)).await.unwrap();
+    assert_eq!(nodes.len(), 0);
+}
+
+#[tokio::test]
+async fn deploy_then_list_projects() {
+    let (mock, node) = setup().await;
+    let client = AgentClient::new(&node, &api_key(), None).unwrap();
+
+    deploy(&client, "web", "20240101000000", &SpawnOptions::default()).await.unwrap();
+
+    assert_eq!(client.list_projects().await.unwrap(), vec!["web"]);
+    assert!(client.container_running("web").await.unwrap());
+
+    let releases = client.list_releases("web").await.unwrap();
+    assert_eq!(releases.len(), 1);
+    assert!(releases[0].active);
+    assert_eq!(mock.state().projects["web"].uploads.len(), 1);
+}
+
+/// Syncs `files` to the project `web` on `client`.
+async fn sync(client: &AgentClient, files: &FileCollection) -> agent_interface::SyncSummary {
+    let manifest = file_sync::manifest(files);
+    client.sync_proj("web", files, &manifest, &mut |_, _| {}).await.unwrap()
+}
+
+#[tokio::test]
+async fn sync_uploads_only_changed_files() {
+    let (mock, node) = setup().await;
+    let client = AgentClient::new(&node, &api_key(), None).unwrap();
+
+    let first = sync(&client, &project_files()).await;
+    assert!(first.full_upload);
+    assert_eq!(first.uploaded, 2);
+
+    let changed = load_project(&[
+        ("Dockerfile", DOCKERFILE),
+        ("main.py", "print('hello again')\n"),
+        ("util.py", "X = 1\n"),
+    ]);
+    let second = sync(&client, &changed).await;
+    assert!(!second.full_upload);
+    assert_eq!((second.uploaded, second.deleted, second.unchanged), (2, 0, 1));
+
+    let mut expected = changed.files.iter().filter(|p| !p.ends_with("Dockerfile")).cloned().collect::<Vec<_>>();
+    expected.sort();
+    let state = mock.state();
+    let project = &state.projects["web"];
+    assert_eq!(project.uploads.len(), 2);
+    assert_eq!(project.uploads[1], expected);
+    assert_eq!(project.files.len(), 3);
+    let main = project.files.iter().find(|(p, _)| p.ends_with("main.py")).unwrap().1;
+    assert_eq!(main, b"print('hello again')\n");
+}
+
+#[tokio::test]
+async fn sync_deletes_removed_files() {
+    let (mock, node) = setup().await;
+    let client = AgentClient::new(&node, &api_key(), None).unwrap();
+    sync(&client, &load_project(&[("Dockerfile", DOCKERFILE), ("main.py", "print('hello')\n"), ("old.py", "\n")])).await;
+
+    let summary = sync(&client, &project_files()).await;
+    assert_eq!((summary.uploaded, summary.deleted, summary.unchanged), (0, 1, 2));
+
+    let mut expected = project_files().files;
+    expected.sort();
+    let state = mock.state();
+    let project = &state.projects["web"];
+    assert_eq!(project.uploads.len(), 1, "nothing changed, so nothing should be uploaded");
+    assert_eq!(project.files.keys().cloned().collect::<Vec<_>>(), expected);
+}
+
+#[tokio::test]
+async fn first_sync_does_not_purge() {
+    let (mock, node) = setup().await;
+    let client = AgentClient::new(&node, &api_key(), None).unwrap();
+
+    sync(&client, &project_files()).await;
+    assert!(!mock.state().requests.iter().any(|r| r.contains("purge-project")));
+}
+
+#[tokio::test]
+async fn sync_without_manifest_support_uploads_everything() {
+    let (mock, node) = setup().await;
+    let client = AgentClient::new(&node, &api_key(), None).unwrap();
+    mock.state().capabilities.retain(|c| c != "manifest");
+
+    sync(&client, &load_project(&[("Dockerfile", DOCKERFILE), ("old.py", "\n")])).await;
+    let summary = sync(&client, &project_files()).await;
+    assert!(summary.full_upload);
+    assert_eq!(summary.uploaded, 2);
+
+    let mut expected = project_files().files;
+    expected.sort();
+    let state = mock.state();
+    assert!(!state.requests.iter().any(|r| r.contains("get-manifest")));
+    assert_eq!(state.projects["web"].files.keys().cloned().collect::<Vec<_>>(), expected);
+}
+
+#[tokio::test]
+async fn sync_without_upload_support_leaves_the_node_alone() {
+    let (mock, node) = setup().await;
+    sync(&AgentClient::new(&node, &api_key(), None).unwrap(), &project_files()).await;
+    mock.state().capabilities.clear();
+    mock.state().requests.clear();
+
+    // Capabilities are cached per client, so this one sees the older agent
+    let client = AgentClient::new(&node, &api_key(), None).unwrap();
+    let files = load_project(&[("Dockerfile", DOCKERFILE)]);
+    let res = client.sync_proj("web", &files, &file_sync::manifest(&files), &mut |_, _| {}).await;
+    assert!(matches!(res, Err(TynkerError::Protocol(_))), "{:?}", res);
+
+    let state = mock.state();
+    assert_eq!(state.requests, vec!["GET /capabilities"]);
+    assert_eq!(state.projects["web"].files.len(), 2);
+}
+
+#[tokio::test]
+async fn interrupted_upload_resumes_with_the_missing_chunks() {
+    let (mock, node) = setup().await;
+    let client = AgentClient::new(&node, &api_key(), None)
+        .unwrap()
+        .with_compression(Compression::None)
+        .with_retry_policy(RetryPolicy::none());
+    // Three chunks without compression
+    let data = "x".repeat(2_500_000);
+    let files = load_project(&[("Dockerfile", DOCKERFILE), ("data.txt", &data)]);
+    client.create_proj("web").await.unwrap();
+
+    mock.state().fail_chunk = Some(1);
+    assert!(client.upload_files("web", &files, &mut |_, _| {}).await.is_err());
+    mock.state().fail_chunk = None;
+
+    let mut progress = vec![];
+    client.upload_files("web", &files, &mut |sent, total| progress.push((sent, total))).await.unwrap();
+
+    let state = mock.state();
+    assert_eq!(state.chunks_stored, vec![0, 1, 2]);
+    assert_eq!(state.projects["web"].uploads.len(), 1);
+    assert!(state.projects["web"].files.values().any(|c| c.len() == data.len()));
+
+    // Progress counts the compressed bytes the node has, ending at the size of the upload
+    assert!(progress.windows(2).all(|w| w[0].0 <= w[1].0), "{:?}", progress);
+    let (sent, total) = *progress.last().unwrap();
+    assert_eq!(sent, total);
+    assert!(sent > data.len() as u64, "{:?}", progress);
+}
+
+#[tokio::test]
+async fn container_running_matches_the_exact_project() {
+    let (_mock, node) = setup().await;
+    let client = AgentClient::new(&node, &api_key(), None).unwrap();
+
+    deploy(&client, "web-old", "20240101000000", &SpawnOptions::default()).await.unwrap();
+    assert!(client.container_running("web-old").await.unwrap());
+    assert!(!client.container_running("web").await.unwrap());
+}
+
+#[tokio::test]
+async fn deploy_passes_env_and_resource_limits() {
+    let (mock, node) = setup().await;
+    let client = AgentClient::new(&node, &api_key(), None).unwrap();
+
+    let opts = SpawnOptions {
+        env: BTreeMap::from([("PORT".to_string(), "8080".to_string())]),
+        resources: Resources { cpus: Some(0.5), memory_mb: Some(256) },
+    };
+    deploy(&client, "api", "20240101000000", &opts).await.unwrap();
+
+    let state = mock.state();
+    let project = &state.projects["api"];
+    assert_eq!(project.env.get("PORT").map(|s| s.as_str()), Some("8080"));
+    assert_eq!(project.resources, opts.resources);
+    assert!(state.requests.iter().any(|r| r.ends_with("/docker/proj/spawn-container-with-options")));
+}
+
+#[tokio::test]
+async fn failed_build_reports_the_log() {
+    let (mock, node) = setup().await;
+    let client = AgentClient::new(&node, &api_key(), None).unwrap();
+    mock.state().fail_builds = true;
+
+    let res = deploy(&client, "web", "20240101000000", &SpawnOptions::default()).await;
+    match res {
+        Err(TynkerError::BuildFailed { project, output }) => {
+            assert_eq!(project, "web");
+            assert!(output.contains("mock build failure"), "{}", output);
+        }
+        res => panic!("expected a build failure, got {:?}", res),
+    }
+    assert!(!mock.state().projects["web"].running);
+}
+
+#[tokio::test]
+async fn rollback_restores_the_previous_release() {
+    let (_mock, node) = setup().await;
+    let client = AgentClient::new(&node, &api_key(), None).unwrap();
+
+    deploy(&client, "web", "20240101000000", &SpawnOptions::default()).await.unwrap();
+    deploy(&client, "web", "20240102000000", &SpawnOptions::default()).await.unwrap();
+
+    client.rollback("web", "20240101000000").await.unwrap();
+    let releases = client.list_releases("web").await.unwrap();
+    let active = releases.iter().find(|r| r.active).unwrap();
+    assert_eq!(active.version, "20240101000000");
+}
+
+#[tokio::test]
+async fn container_actions_and_logs() {
+    let (_mock, node) = setup().await;
+    let client = AgentClient::new(&node, &api_key(), None).unwrap();
+    deploy(&client, "web", "20240101000000", &SpawnOptions::default()).await.unwrap();
+
+    client.container_action("web", ContainerAction::Stop).await.unwrap();
+    assert!(!client.container_running("web").await.unwrap());
+    client.container_action("web", ContainerAction::Start).await.unwrap();
+    assert!(client.container_running("web").await.unwrap());
+
+    let mut lines = vec![];
+    let opts = LogOptions { tail: Some(2), ..Default::default() };
+    client.stream_logs("web", &opts, |l| lines.push(l.to_string())).await.unwrap();
+    assert_eq!(lines, vec!["log line 4", "log line 5"]);
+
+    let res = client.container_action("missing", ContainerAction::Stop).await;
+    assert!(matches!(res, Err(TynkerError::ProjectNotFound(_))), "{:?}", res);
+}
+
+#[tokio::test]
+async fn wrong_api_key_is_unauthorized() {
+    let (_mock, node) = setup().await;
+    let client = AgentClient::new(&node, "not-the-key", None)
+        .unwrap()
+        .with_retry_policy(RetryPolicy::none());
+
+    let res = client.list_projects().await;
+    assert!(matches!(res, Err(TynkerError::Unauthorized(_))), "{:?}", res);
+}
+
+#[tokio::test]
+async fn ping_retries_a_bounded_number_of_times() {
+    let (_mock, mut node) = setup().await;
+    node.addr = "http://127.0.0.1:9".to_string();
+    let client = AgentClient::new(&node, &api_key(), None).unwrap();
+
+    // Refused connections fail at once, so the time taken is the backoff between attempts
+    let start = std::time::Instant::now();
+    let res = client.ping().await;
+    assert!(matches!(res, Err(TynkerError::Retried { attempts: 3, .. })), "{:?}", res);
+    assert!(start.elapsed() < std::time::Duration::from_secs(2), "{:?}", start.elapsed());
+}
+
+#[tokio::test]
+async fn api_key_override_is_sent_to_nodes() {
+    let (_mock, node) = setup().await;
+    let mut gstate = GlobalState::new("test", "http://127.0.0.1:9", EMAIL, PASSWORD, "stale-key");
+    gstate.api_key_override = Some(api_key());
+
+    let client = gstate.agent_client(&node)
+        .unwrap()
+        .with_retry_policy(RetryPolicy::none());
+    client.list_projects().await.unwrap();
+}
+
+#[tokio::test]
+async fn api_key_works_without_a_saved_profile() {
+    let (mock, _) = setup().await;
+    assert!(!GlobalState::exists("ci-without-profile"));
+
+    // No auth server is needed, the nodes come from their addresses
+    let mut gstate = GlobalState::from_api_key("ci-without-profile", "http://127.0.0.1:9", EMAIL, &api_key());
+    gstate.resolve_nodes(&[format!("web={}", mock.addr()), mock.addr().to_string()]).await.unwrap();
+    gstate.fetch_nodes().await.unwrap();
+
+    let names = gstate.nodes.iter().map(|n| n.name.as_str()).collect::<Vec<&str>>();
+    assert_eq!(names, vec!["web", "mock-node"]);
+    assert_eq!(gstate.nodes[0].node_id, "mock-node");
+
+    let client = gstate.agent_client(&gstate.nodes[0]).unwrap();
+    deploy(&client, "web", "20240101000000", &SpawnOptions::default()).await.unwrap();
+    assert!(gstate.save().is_err(), "a state without a profile must never be written");
+    assert!(!GlobalState::exists("ci-without-profile"));
+}
+
+/// Promotes every canary.
+struct Promote;
+
+impl Reporter for Promote {
+    fn promote(&self, _canary: &str, _version: &str, _remaining: usize) -> bool {
+        true
+    }
+}
+
+/// Clients for `count` nodes, each with its own mock agent so that nodes deployed to at the
+/// same time don't share projects.
+async fn clients(count: usize) -> (Vec<MockServer>, Vec<AgentClient>) {
+    let mut mocks = vec![];
+    let mut clients = vec![];
+    for i in 1..=count {
+        let mock = MockServer::start(&api_key()).await;
+        let node = mock.add_node(EMAIL, &format!("node-{}", i));
+        clients.push(AgentClient::new(&node, &api_key(), None).unwrap());
+        mocks.push(mock);
+    }
+    (mocks, clients)
+}
+
+fn settings(strategy: Strategy) -> DeployConfig {
+    DeployConfig {
+        strategy,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn rolling_deployment_reaches_every_node() {
+    let (_mocks, clients) = clients(2).await;
+    let strategy = Strategy::Rolling { batch_size: 1, wait_secs: 0 };
+
+    let report = Deployment::new(proj_config("web"), project_files(), clients)
+        .with_settings(settings(strategy))
+        .with_author(EMAIL, "test")
+        .run(Arc::new(Silent))
+        .await;
+
+    assert!(report.succeeded(), "{:?}", report.failures);
+    assert_eq!(report.record.deployed_by, EMAIL);
+    for node in report.record.nodes.iter() {
+        assert_eq!(node.spawn, StepOutcome::Succeeded);
+        assert_eq!(node.health, StepOutcome::Succeeded);
+    }
+}
+
+#[tokio::test]
+async fn canary_is_not_promoted_without_approval() {
+    let (_mocks, clients) = clients(3).await;
+    let strategy = Strategy::Canary { auto_promote: false, wait_secs: 0 };
+
+    let report = Deployment::new(proj_config("web"), project_files(), clients)
+        .with_settings(settings(strategy))
+        .run(Arc::new(Silent))
+        .await;
+
+    assert_eq!(report.record.status(), "partial");
+    assert_eq!(report.record.nodes[0].spawn, StepOutcome::Succeeded);
+    assert_eq!(report.record.nodes[1].transfer, StepOutcome::Skipped);
+    assert_eq!(report.record.nodes[2].transfer, StepOutcome::Skipped);
+}
+
+#[tokio::test]
+async fn approved_canary_is_promoted() {
+    let (_mocks, clients) = clients(3).await;
+    let strategy = Strategy::Canary { auto_promote: false, wait_secs: 0 };
+
+    let report = Deployment::new(proj_config("web"), project_files(), clients)
+        .with_settings(settings(strategy))
+        .run(Arc::new(Promote))
+        .await;
+
+    assert!(report.succeeded(), "{:?}", report.failures);
+}
+
+#[tokio::test]
+async fn failed_batch_halts_the_rollout() {
+    let (mocks, clients) = clients(2).await;
+    for mock in mocks.iter() {
+        mock.state().fail_builds = true;
+    }
+    let strategy = Strategy::Rolling { batch_size: 1, wait_secs: 0 };
+
+    let report = Deployment::new(proj_config("web"), project_files(), clients)
+        .with_settings(settings(strategy))
+        .run(Arc::new(Silent))
+        .await;
+
+    assert_eq!(report.record.status(), "failed");
+    assert_eq!(report.failures.len(), 1);
+    assert!(matches!(report.record.nodes[0].build, StepOutcome::Failed(_)));
+    assert_eq!(report.record.nodes[1].transfer, StepOutcome::Skipped);
+}
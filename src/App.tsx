@@ -2,7 +2,7 @@ import { BrowserRouter as Router, Route, Routes } from 'react-router-dom';
 
 // Components
 import { SidePanel, InitialRouter } from './components/molecules/molecules';
-import { NodeMgmtPage, NodeInfoPage, DataviewPage, PrebuiltsPage } from "./components/organisms/organisms";
+import { NodeMgmtPage, NodeInfoPage, DataviewPage, PrebuiltsPage, HistoryPage } from "./components/organisms/organisms";
 
 // Styling
 import "./App.css";
@@ -18,6 +18,7 @@ function App() {
 					<Route path="/node/:id" element={<NodeInfoPage/>}/>
 					<Route path="/prebuilts" element={<PrebuiltsPage/>}/>
 					<Route path="/dataview" element={<DataviewPage/>}/>
+					<Route path="/history" element={<HistoryPage/>}/>
 					<Route path="*" element={<p>Not Found</p>}/>
 				</Routes>
 			</Router>
@@ -1,22 +1,25 @@
-import { useEffect, useState, MouseEvent } from 'react';
+import { useEffect, useRef, useState, MouseEvent } from 'react';
 import { useLocation, useNavigate } from 'react-router-dom';
 import { FaCog } from 'react-icons/fa';
-import { Pause, Play, Trash2, Cpu, HardDrive, Network, Clock } from 'lucide-react';
+import { Pause, Play, RotateCw, Hammer, Trash2, ScrollText, X, Cpu, HardDrive, Network, Clock } from 'lucide-react';
 
 import SidePanelStyles from "./styles/SidePanelStyles.module.css";
 import NodeInfoCardStyles from "./styles/NodeInfoCardStyles.module.css";
 import ContainerCardStyles from "./styles/ContainerCardStyles.module.css"
 import ComingSoonStyles from "./styles/ComingSoonStyles.module.css";
+import LogViewerStyles from "./styles/LogViewerStyles.module.css";
 
 import { shorten } from '../utils';
 import { Icon, ContextMenu } from "../atoms/atoms";
 import { invoke } from '@tauri-apps/api';
+import { listen } from '@tauri-apps/api/event';
 
 export function SidePanel() {
     var pageList = [
         {url: '/nodes', icon: '/icons/server-icon.svg', isSelected: false},
         {url: '/prebuilts', icon: '/icons/docker-icon.svg', isSelected: false},
         {url: '/dataview', icon: '/icons/dataview-icon.svg', isSelected: false},
+        {url: '/history', icon: '/icons/history-icon.svg', isSelected: false},
     ];
     
     let location = useLocation().pathname;
@@ -106,26 +109,65 @@ export function NodeInfoCard(props: NodeInfoCardProps) {
 }
 
 interface ContainerCardProps {
+    nodeId: string,
     imgName: string,
     cpu_perc: string;
     mem_perc: string;
     command: string;
     status: string;
     ports: string;
+    onChange?: () => void;
 }
 export function ContainerCard(props: ContainerCardProps) {
+	// Images are tagged with the release, e.g. `proj__tyb_image:20240612093000`
+	const projName = props.imgName.split(':')[0].replace('__tyb_image', '');
+	const [pending, setPending] = useState(() => false);
+	const [showLogs, setShowLogs] = useState(() => false);
+	const [showBuild, setShowBuild] = useState(() => false);
+
+	const runAction = (cmd: string) => {
+		setPending(true);
+		invoke(cmd, {'nodeId': props.nodeId, 'projName': projName})
+			.then(() => {
+				if (props.onChange) {
+					props.onChange();
+				}
+			})
+			.catch(err => {
+				console.log(err);
+			})
+			.finally(() => {
+				setPending(false);
+			});
+	}
+
+	const remove = () => {
+		if (window.confirm(`Remove the container of ${projName}? The project's files and images are kept.`)) {
+			runAction('remove_container');
+		}
+	}
+
 	return (
 		<div className={ContainerCardStyles.container}>
 			<div className={ContainerCardStyles.header}>
-				<h2 className={ContainerCardStyles.projectName}>{props.imgName.replace('__tyb_image', '')}</h2>
+				<h2 className={ContainerCardStyles.projectName}>{projName}</h2>
 				<div className={ContainerCardStyles.buttonsContainer}>
-					<button className={ContainerCardStyles.iconButton} style={{ color: 'yellow' }}>
+					<button className={ContainerCardStyles.iconButton} style={{ color: 'yellow' }} disabled={pending} onClick={() => runAction('stop_container')}>
 						<Pause size={20} />
 					</button>
-					<button className={ContainerCardStyles.iconButton} style={{ color: 'green' }}>
+					<button className={ContainerCardStyles.iconButton} style={{ color: 'green' }} disabled={pending} onClick={() => runAction('start_container')}>
 						<Play size={20} />
 					</button>
-					<button  className={ContainerCardStyles.iconButton}style={{ color: 'red' }}>
+					<button className={ContainerCardStyles.iconButton} style={{ color: 'deepskyblue' }} disabled={pending} onClick={() => runAction('restart_container')}>
+						<RotateCw size={20} />
+					</button>
+					<button className={ContainerCardStyles.iconButton} style={{ color: 'orange' }} disabled={pending || showBuild} onClick={() => setShowBuild(true)}>
+						<Hammer size={20} />
+					</button>
+					<button className={ContainerCardStyles.iconButton} style={{ color: 'white' }} onClick={() => setShowLogs(!showLogs)}>
+						<ScrollText size={20} />
+					</button>
+					<button  className={ContainerCardStyles.iconButton}style={{ color: 'red' }} disabled={pending} onClick={remove}>
 						<Trash2 size={20} />
 					</button>
 				</div>
@@ -160,9 +202,134 @@ export function ContainerCard(props: ContainerCardProps) {
 					</div>
 				</div>
 			</div>
+			{showLogs && <LogViewer nodeId={props.nodeId} projName={projName} onClose={() => setShowLogs(false)}/>}
+			{showBuild && <BuildViewer nodeId={props.nodeId} projName={projName} onClose={() => setShowBuild(false)}/>}
 		</div>
 	);
 }
+
+interface LogEvent {
+    node_id: string,
+    proj_name: string,
+    line: string,
+}
+
+interface LogEndEvent {
+    node_id: string,
+    proj_name: string,
+    error?: {code: string, message: string, hint?: string},
+}
+
+interface LogViewerProps {
+    nodeId: string,
+    projName: string,
+    onClose: () => void,
+}
+export function LogViewer(props: LogViewerProps) {
+    const [lines, setLines] = useState<string[]>(() => []);
+    const [status, setStatus] = useState<string | null>(() => null);
+    const bottomRef = useRef<HTMLDivElement>(null);
+
+    useEffect(() => {
+        const isOurs = (p: {node_id: string, proj_name: string}) => p.node_id == props.nodeId && p.proj_name == props.projName;
+
+        const unlistenLog = listen<LogEvent>('container-log', event => {
+            if (isOurs(event.payload)) {
+                // Only keep the most recent lines so long running containers don't eat memory
+                setLines(prev => [...prev, event.payload.line].slice(-1000));
+            }
+        });
+        const unlistenEnd = listen<LogEndEvent>('container-log-end', event => {
+            if (isOurs(event.payload)) {
+                setStatus(event.payload.error ? event.payload.error.message : 'Log stream closed');
+            }
+        });
+
+        invoke('start_log_stream', {'nodeId': props.nodeId, 'projName': props.projName, 'tail': 200})
+            .catch(err => {
+                setStatus(err.message ?? `${err}`);
+            });
+
+        return () => {
+            unlistenLog.then(f => f());
+            unlistenEnd.then(f => f());
+            invoke('stop_log_stream', {'nodeId': props.nodeId, 'projName': props.projName});
+        }
+    }, [props.nodeId, props.projName]);
+
+    useEffect(() => {
+        bottomRef.current?.scrollIntoView({ behavior: 'smooth' });
+    }, [lines]);
+
+    return (<>
+        <div className={LogViewerStyles.container} onClick={e => e.stopPropagation()}>
+            <div className={LogViewerStyles.header}>
+                <span>Logs: {props.projName}</span>
+                <X size={18} className={LogViewerStyles.close} onClick={props.onClose}/>
+            </div>
+            <div className={LogViewerStyles.lines}>
+                {lines.map((l, i) => (<pre key={i} className={LogViewerStyles.line}>{l}</pre>))}
+                {status && <p className={LogViewerStyles.status}>{status}</p>}
+                <div ref={bottomRef}/>
+            </div>
+        </div>
+    </>)
+}
+
+interface BuildLogEvent {
+    node_id: string,
+    line: string,
+}
+
+interface BuildViewerProps {
+    nodeId: string,
+    projName: string,
+    onClose: () => void,
+}
+export function BuildViewer(props: BuildViewerProps) {
+    const [lines, setLines] = useState<string[]>(() => []);
+    const [status, setStatus] = useState<string | null>(() => null);
+    const bottomRef = useRef<HTMLDivElement>(null);
+
+    useEffect(() => {
+        // Build events only carry the node, so wait until we're listening before starting the build
+        const unlisten = listen<BuildLogEvent>('build-log', event => {
+            if (event.payload.node_id == props.nodeId) {
+                setLines(prev => [...prev, event.payload.line]);
+            }
+        });
+
+        unlisten.then(() => invoke('build_image', {'nodeId': props.nodeId, 'projName': props.projName}))
+            .then(() => {
+                setStatus('Build finished');
+            })
+            .catch(err => {
+                setStatus(err.message ?? `${err}`);
+            });
+
+        return () => {
+            unlisten.then(f => f());
+        }
+    }, [props.nodeId, props.projName]);
+
+    useEffect(() => {
+        bottomRef.current?.scrollIntoView({ behavior: 'smooth' });
+    }, [lines]);
+
+    return (<>
+        <div className={LogViewerStyles.container} onClick={e => e.stopPropagation()}>
+            <div className={LogViewerStyles.header}>
+                <span>Build: {props.projName}</span>
+                <X size={18} className={LogViewerStyles.close} onClick={props.onClose}/>
+            </div>
+            <div className={LogViewerStyles.lines}>
+                {lines.map((l, i) => (<pre key={i} className={LogViewerStyles.line}>{l}</pre>))}
+                <p className={LogViewerStyles.status}>{status ?? 'Building...'}</p>
+                <div ref={bottomRef}/>
+            </div>
+        </div>
+    </>)
+}
   
 
 interface ComingSoonProps {
@@ -5,10 +5,11 @@ import { FaExclamationTriangle, FaSync } from 'react-icons/fa';
 
 import NodeMgmtPageStyles from "./styles/NodeMgmtPageStyles.module.css";
 import NodeInfoPageStyles from "./styles/NodeInfoPageStyles.module.css";
+import HistoryPageStyles from "./styles/HistoryPageStyles.module.css";
 
 import { NodeInfoCard, ContainerCard, ComingSoon } from "../molecules/molecules"
 import { Loader } from '../atoms/atoms';
-import { Node, NodeDiags } from "../schemas";
+import { Node, NodeDiags, Profile, DeployRecord, NodeOutcome } from "../schemas";
 import { ContainerStats, shorten } from '../utils';
 
 
@@ -16,6 +17,7 @@ export function NodeMgmtPage() {
     const [nodes, setNodes] = useState<Node[]>(() => []);
     const [render, _setRender] = useState(() => 0);
     const [fetchedNodes, setFetchedDNodes] = useState(() => false);
+    const [profiles, setProfiles] = useState<Profile[]>(() => []);
 
     useEffect(() => {
         invoke<Node[]>("list_nodes").then(v => {
@@ -24,12 +26,38 @@ export function NodeMgmtPage() {
         });
     }, [render]);
 
+    useEffect(() => {
+        invoke<Profile[]>("list_profiles").then(v => {
+            setProfiles(v);
+        });
+    }, [render]);
+
+    const switchProfile = (profile: string) => {
+        setFetchedDNodes(false);
+        invoke('switch_profile', {'profile': profile})
+            .then(() => {
+                window.location.reload();
+            })
+            .catch(err => {
+                console.log(err);
+                setFetchedDNodes(true);
+            });
+    }
+
 
     return (<>
         <div className={NodeMgmtPageStyles.container}>
 
             <div className={NodeMgmtPageStyles.title_container} onClick={() =>window.location.reload()}>
                 {/* <img src="/images/tynkerbase-banner-2.png"/> */}
+                {profiles.length > 1 && <select
+                    className={NodeMgmtPageStyles.profile_select}
+                    value={profiles.find(p => p.active == 'true')?.name}
+                    onClick={e => e.stopPropagation()}
+                    onChange={e => switchProfile(e.target.value)}
+                >
+                    {profiles.map(p => (<option key={p.name} value={p.name}>{p.name}</option>))}
+                </select>}
                 <div className={NodeMgmtPageStyles.title_refresh_container}>
                     <FaSync className={NodeMgmtPageStyles.title_refresh_icon}/>
                     <p>Refresh</p>
@@ -87,13 +115,14 @@ export function NodeInfoPage() {
         })
     }, [id])
 
+    const [refresh, setRefresh] = useState(() => 0);
     useEffect (() => {
         invoke<ContainerStats[]>('get_container_stats', {nodeId: id}).then(res => {
             console.log(res);
             setContainers(res);
             setFetchedData(true);
         })
-    }, [id])
+    }, [id, refresh])
 
 
     const formatMem = (mem: string | null | undefined) => {
@@ -167,12 +196,15 @@ export function NodeInfoPage() {
                 {
                     containers.map(c => {
                     return <ContainerCard 
+                        key={c.container_id}
+                        nodeId={id ?? 'unknown'}
                         imgName={c.image} 
                         cpu_perc={c.cpu_perc} 
                         mem_perc={c.mem_perc} 
                         command={c.command} 
                         status={c.status} 
                         ports={c.ports}
+                        onChange={() => setRefresh(refresh + 1)}
                     />
                 })
                 }
@@ -182,6 +214,112 @@ export function NodeInfoPage() {
     </>)
 }
 
+export function HistoryPage() {
+    const [projects, setProjects] = useState<string[]>(() => []);
+    const [project, setProject] = useState<string | null>(() => null);
+    const [records, setRecords] = useState<DeployRecord[]>(() => []);
+    const [fetchedData, setFetchedData] = useState(() => false);
+    const [err, setErr] = useState<string | null>(() => null);
+
+    useEffect(() => {
+        invoke<string[]>('list_history_projects').then(v => {
+            setProjects(v);
+            if (v.length > 0) {
+                setProject(v[0]);
+            }
+            else {
+                setFetchedData(true);
+            }
+        });
+    }, []);
+
+    useEffect(() => {
+        if (project == null) {
+            return;
+        }
+        setFetchedData(false);
+        invoke<DeployRecord[]>('get_history', {projName: project})
+            .then(v => {
+                setRecords(v);
+                setFetchedData(true);
+            })
+            .catch(e => {
+                setErr(`Error getting deployment history: ${e}`);
+                setFetchedData(true);
+            });
+    }, [project]);
+
+    const nodeStatus = (n: NodeOutcome) => {
+        for (const [step, outcome] of [['transfer', n.transfer], ['build', n.build], ['spawn', n.spawn]] as const) {
+            if (typeof outcome != 'string') {
+                return {text: `failed to ${step}`, title: outcome.failed, color: 'red'};
+            }
+        }
+        if (typeof n.health != 'string') {
+            const text = n.rolled_back_to ? `unhealthy, rolled back to ${n.rolled_back_to}` : 'unhealthy';
+            return {text: text, title: n.health.failed, color: 'red'};
+        }
+        return {text: 'ok', title: '', color: 'green'};
+    }
+
+    return (<>
+        <div className={HistoryPageStyles.container}>
+            {err && <div className={NodeInfoPageStyles.err_msg}>
+                <p>
+                    <FaExclamationTriangle style={{ color: 'black', marginRight: '5px' }} />
+                    {err}
+                </p>
+            </div>}
+
+            {projects.length > 0 && <select
+                className={HistoryPageStyles.project_select}
+                value={project ?? undefined}
+                onChange={e => setProject(e.target.value)}
+            >
+                {projects.map(p => (<option key={p} value={p}>{p}</option>))}
+            </select>}
+
+            {!fetchedData && <div>
+                <Loader/>
+            </div>}
+
+            {(fetchedData && records.length == 0) && <div>
+                <p>No Deployments Found</p>
+            </div>}
+
+            {records.length > 0 && <table className={HistoryPageStyles.table}>
+                <thead>
+                    <tr>
+                        <th>Release</th>
+                        <th>Deployed At</th>
+                        <th>By</th>
+                        <th>Commit</th>
+                        <th>Manifest</th>
+                        <th>Nodes</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    {records.map(r => (<tr key={`${r.version}-${r.timestamp}`}>
+                        <td>{r.environment ? `${r.version} (${r.environment})` : r.version}</td>
+                        <td>{new Date(r.timestamp).toLocaleString()}</td>
+                        <td>{r.deployed_by}</td>
+                        <td>{r.git_commit ? shorten(r.git_commit, 10) : '-'}</td>
+                        <td>{shorten(r.manifest_hash, 12)}</td>
+                        <td>
+                            {r.nodes.map(n => {
+                                const status = nodeStatus(n);
+                                return <p key={n.node} title={status.title}>
+                                    {n.node}: <span style={{color: status.color}}>{status.text}</span>
+                                </p>
+                            })}
+                        </td>
+                    </tr>))}
+                </tbody>
+            </table>}
+        </div>
+    </>)
+}
+
 export function PrebuiltsPage() {
     return (<>
         <ComingSoon message={['Coming Soon!', 'Will provide prebuild containers (Mongo, Postgres, Redis, etc) that can be launched with a single button click.']}/>
@@ -6,6 +6,33 @@ export interface Node {
     status: string,
 }
 
+export interface Profile {
+    name: string,
+    active: string,
+}
+
+export interface NodeOutcome {
+    node: string,
+    // "succeeded", "skipped" or {"failed": "<error>"}
+    transfer: string | {failed: string},
+    build: string | {failed: string},
+    spawn: string | {failed: string},
+    health: string | {failed: string},
+    rolled_back_to?: string,
+}
+
+export interface DeployRecord {
+    project: string,
+    version: string,
+    timestamp: string,
+    deployed_by: string,
+    profile: string,
+    environment?: string,
+    git_commit?: string,
+    manifest_hash: string,
+    nodes: NodeOutcome[],
+}
+
 export interface NodeDiags {
     node_id: string,
     name: string,